@@ -0,0 +1,81 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use day07::amp::max_thruster_signal;
+use day07::gravity::find_noun_verb;
+use day07::icm::{parse_program, Processor, RunState};
+use std::fs;
+
+// counts mem[17] down from 100000 to zero, mostly executing add, mul and jumps
+const COUNTDOWN: &str = "1,17,18,17,1002,17,1,19,1006,17,16,1105,1,0,99,99,99,100000,-1,0";
+
+fn bench_countdown(c: &mut Criterion) {
+    let program = parse_program(COUNTDOWN).unwrap();
+    c.bench_function("countdown", |b| {
+        b.iter(|| {
+            let mut p = Processor::buffered(black_box(program.clone()));
+            assert_eq!(RunState::Halted, p.run());
+        })
+    });
+}
+
+fn bench_day07_series(c: &mut Criterion) {
+    let input = fs::read_to_string("input").expect("Could not read input file.");
+    let program = parse_program(&input).unwrap();
+    c.bench_function("day07 series", |b| {
+        b.iter(|| max_thruster_signal(black_box(&program), 0..=4, false))
+    });
+}
+
+// brute-forces day 2 part 2, running the program a few thousand times
+fn bench_day02_search(c: &mut Criterion) {
+    let input = fs::read_to_string("../day02/input").expect("Could not read input file.");
+    let program = parse_program(&input).unwrap();
+    c.bench_function("day02 search", |b| {
+        b.iter(|| find_noun_verb(black_box(&program), 19690720))
+    });
+}
+
+// writes to a few addresses near the memory cap, the dense memory grows to 32 MB
+const HIGH_WRITES: &str = "1101,1,2,4000000,1101,3,4,3000000,1101,5,6,3500000,4,4000000,99";
+const FAR_WRITES: &str =
+    "1101,1,2,4000000000,1101,3,4,3000000000,1101,5,6,3500000000,4,4000000000,99";
+
+fn bench_high_writes(c: &mut Criterion) {
+    let program = parse_program(HIGH_WRITES).unwrap();
+    for sparse in [false, true] {
+        let name = if sparse {
+            "high writes sparse"
+        } else {
+            "high writes dense"
+        };
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                let mut p = Processor::builder()
+                    .program(black_box(program.clone()))
+                    .sparse(sparse)
+                    .build();
+                assert_eq!(RunState::Halted, p.run());
+            })
+        });
+    }
+
+    // far beyond the cap, which only sparse memory can address
+    let program = parse_program(FAR_WRITES).unwrap();
+    c.bench_function("far writes sparse", |b| {
+        b.iter(|| {
+            let mut p = Processor::builder()
+                .program(black_box(program.clone()))
+                .sparse(true)
+                .build();
+            assert_eq!(RunState::Halted, p.run());
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_countdown,
+    bench_day07_series,
+    bench_day02_search,
+    bench_high_writes
+);
+criterion_main!(benches);
@@ -1,30 +1,311 @@
 pub mod icm {
+    #[cfg(feature = "serde")]
+    use serde::{Deserialize, Serialize};
+    use std::collections::{HashMap, HashSet, VecDeque};
     use std::convert::TryInto;
-    use std::sync::mpsc::{Receiver, Sender};
+    use std::error::Error;
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::io::Write;
+    use std::num::ParseIntError;
+    use std::ops::{Index, IndexMut};
+    use std::path::Path;
+    use std::sync::mpsc::{
+        channel, sync_channel, Receiver, RecvTimeoutError, SendError, Sender, SyncSender,
+        TryRecvError,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// The machine word of the Intcode computer.
+    pub type Word = i64;
+
+    /// Default number of memory words a program can grow its memory to.
+    pub const DEFAULT_MAX_MEMORY: usize = 4 << 20;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum IcmError {
+        /// An instruction word that doesn't decode, with the IP it was found at.
+        UnknownOpcode(Word, usize),
+        /// Running stopped on an error. Carries the IP, the error and the
+        /// values output since the current run started.
+        Faulted {
+            at: usize,
+            error: Box<IcmError>,
+            outputs: Vec<Word>,
+        },
+        /// A negative instruction word, with the IP it was found at.
+        InvalidInstruction(Word, usize),
+        /// An instruction with a write parameter in immediate mode, with its IP.
+        InvalidWriteParam(usize),
+        /// The input channel was closed while waiting for a value.
+        RecvError,
+        /// The output channel was closed while sending a value.
+        SendError,
+        /// No input arrived within the configured timeout.
+        RecvTimeout,
+        /// A jump to a negative address, with the target.
+        InvalidJumpTarget(Word),
+        /// A position or relative parameter referring to a negative address,
+        /// with the address.
+        NegativeAddress(Word),
+        /// An address that can't be used, with the address: an IP beyond the
+        /// end of memory, a write beyond the memory cap, or any access beyond
+        /// the memory of a `FixedProcessor`, including loading a longer program.
+        AddressOutOfBounds(usize),
+        /// The configured maximum number of instructions was executed.
+        InstructionLimitExceeded(u64),
+        /// An addition or multiplication overflowed with checked arithmetic
+        /// enabled, with the IP of the instruction.
+        ArithmeticOverflow(usize),
+        /// The processor was started without a program.
+        EmptyProgram,
+    }
+
+    impl IcmError {
+        /// The error itself, or the one a `Faulted` error wraps.
+        pub fn cause(&self) -> &IcmError {
+            match self {
+                IcmError::Faulted { error, .. } => error,
+                e => e,
+            }
+        }
+    }
+
+    impl fmt::Display for IcmError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                IcmError::UnknownOpcode(op, ip) => write!(f, "unknown opcode {} at {}", op, ip),
+                IcmError::Faulted { error, outputs, .. } => {
+                    write!(f, "{} after {} outputs", error, outputs.len())
+                }
+                IcmError::InvalidInstruction(val, ip) => {
+                    write!(f, "invalid instruction {} at {}", val, ip)
+                }
+                IcmError::InvalidWriteParam(ip) => {
+                    write!(f, "write parameter in immediate mode at {}", ip)
+                }
+                IcmError::RecvError => write!(f, "input channel closed"),
+                IcmError::SendError => write!(f, "output channel closed"),
+                IcmError::RecvTimeout => write!(f, "timed out waiting for input"),
+                IcmError::InvalidJumpTarget(target) => write!(f, "invalid jump target {}", target),
+                IcmError::NegativeAddress(addr) => write!(f, "negative address {}", addr),
+                IcmError::AddressOutOfBounds(addr) => write!(f, "address {} out of bounds", addr),
+                IcmError::InstructionLimitExceeded(max) => {
+                    write!(f, "instruction limit of {} exceeded", max)
+                }
+                IcmError::ArithmeticOverflow(ip) => write!(f, "arithmetic overflow at {}", ip),
+                IcmError::EmptyProgram => write!(f, "empty program"),
+            }
+        }
+    }
+
+    impl Error for IcmError {}
+
+    /// Outcome of executing a single instruction.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum StepResult {
+        /// The instruction executed and the machine can continue.
+        Continued,
+        /// The machine reached a halt instruction.
+        Halted,
+        /// The machine is waiting for input.
+        Blocked,
+    }
+
+    /// Callback receiving the IP and decoded instruction before it executes.
+    pub type TraceFn = Box<dyn FnMut(usize, &Instr) + Send>;
+
+    /// Callback receiving the address and new value of a write into code.
+    pub type CodeWriteFn = Box<dyn FnMut(usize, Word) + Send>;
+
+    /// Callback receiving the address of a read beyond the initial program
+    /// from memory the program didn't write to.
+    pub type UninitReadFn = Box<dyn FnMut(usize) + Send>;
+
+    /// Callback supplying input values when no other input is pending.
+    pub type InputFn = Box<dyn FnMut() -> Word + Send>;
+
+    /// Custom implementation of an opcode. It has to advance the IP itself.
+    pub type OpcodeFn = Box<dyn FnMut(&mut Processor) + Send>;
+
+    /// Reason a call to `run` returned.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum RunState {
+        /// The program executed a halt instruction.
+        Halted,
+        /// The program is waiting for input that isn't available yet.
+        AwaitingInput,
+        /// Execution stopped on an error. Runs report it as `IcmError::Faulted`
+        /// with the output produced so far, see `error` for the cause.
+        Error(IcmError),
+        /// The IP reached a breakpoint.
+        Breakpoint(usize),
+        /// The receiver of the output channel is gone. The output instruction
+        /// that failed is retried when the processor runs again.
+        OutputClosed,
+        /// No input arrived within the timeout passed to `run_with_timeout`.
+        /// The input instruction is retried when the processor runs again.
+        Timeout,
+        /// `run_steps` executed all the instructions it was allowed to.
+        Paused,
+        /// An instruction changed the value at a watched address. The machine
+        /// stopped right after that instruction.
+        Watchpoint { addr: usize, old: Word, new: Word },
+        /// The sender of the input channel is gone, so no more input will
+        /// arrive. The input instruction is retried when the processor runs
+        /// again, e.g. after `set_input`.
+        InputClosed,
+    }
+
+    impl RunState {
+        /// The error execution stopped on, without the outputs of a
+        /// `Faulted` error.
+        pub fn error(&self) -> Option<&IcmError> {
+            match self {
+                RunState::Error(e) => Some(e.cause()),
+                _ => None,
+            }
+        }
+    }
+
+    /// Snapshot of the CPU and memory state of a processor, without its I/O.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct MachineState {
+        pub ip: usize,
+        pub relative_base: Word,
+        pub mem: Vec<Word>,
+        /// Cells stored beyond `mem` by a processor with sparse memory, by address.
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub sparse: Vec<(usize, Word)>,
+    }
+
+    /// In-memory snapshot taken by `checkpoint`, which can be rolled back to
+    /// any number of times.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Checkpoint {
+        ip: usize,
+        relative_base: Word,
+        mem: Vec<Word>,
+        sparse: Option<HashMap<usize, Word>>,
+    }
 
     #[derive(Debug)]
-    enum Param {
+    pub enum Param {
         Position(usize),
-        Immediate(i32),
+        Immediate(Word),
+        Relative(Word),
+    }
+
+    impl fmt::Display for Param {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Param::Position(n) => write!(f, "@{}", n),
+                Param::Immediate(n) => write!(f, "#{}", n),
+                Param::Relative(n) => write!(f, "~{}", n),
+            }
+        }
     }
 
     /*
     Parses a parameter.
-    nth   - Number of the parameter, beginning by zero.
-    pcode - Paramter mode code. A number where each digit specifies a parameter mode.
-    val   - Value of the parameter.
+    word - Instruction word, its digits above the opcode are the modes.
+    addr - Address of the instruction, for errors.
+    nth  - Number of the parameter, beginning by zero.
+    val  - Value of the parameter.
+    Fails on an unknown mode or a negative position.
+     */
+    fn parse_param(word: Word, addr: usize, nth: u32, val: Word) -> Result<Param, IcmError> {
+        match (word / 100 / Word::pow(10, nth)) % 10 {
+            0 => val
+                .try_into()
+                .map(Param::Position)
+                .map_err(|_| IcmError::NegativeAddress(val)),
+            1 => Ok(Param::Immediate(val)),
+            // a negative offset is fine, the resolved address is checked later
+            2 => Ok(Param::Relative(val)),
+            _ => Err(IcmError::UnknownOpcode(word, addr)),
+        }
+    }
+
+    /*
+    Decodes the instruction at an address of a memory image.
+    mem  - Memory holding the instruction.
+    addr - Address of the instruction word.
+    Parameters beyond the end of `mem` read as zero. Fails if `mem` is empty,
+    `addr` is out of bounds, the instruction word is negative, a parameter
+    mode is unknown, a position parameter is negative or a parameter that is
+    written to is in immediate mode.
      */
-    fn parse_param(nth: u32, pcode: i32, val: i32) -> Param {
-        if (pcode / 10_i32.pow(nth)) % 10 == 0 {
-            let p: usize = val.try_into().unwrap();
-            Param::Position(p)
-        } else {
-            Param::Immediate(val)
+    pub fn decode_instruction(mem: &[Word], addr: usize) -> Result<Instr, IcmError> {
+        if mem.is_empty() {
+            return Err(IcmError::EmptyProgram);
         }
+        decode_with(|addr| mem.get(addr).copied(), addr)
+    }
+
+    /// Decodes the instruction at `addr` from memory read through `fetch`,
+    /// which returns `None` for cells that don't exist.
+    fn decode_with(fetch: impl Fn(usize) -> Option<Word>, addr: usize) -> Result<Instr, IcmError> {
+        let val = match fetch(addr) {
+            Some(val) => val,
+            None => return Err(IcmError::AddressOutOfBounds(addr)),
+        };
+        // the remainder of a negative word would be a negative opcode
+        if val < 0 {
+            return Err(IcmError::InvalidInstruction(val, addr));
+        }
+        let opcode = val % 100;
+        let param = |nth: u32| {
+            let v = fetch(addr + 1 + nth as usize).unwrap_or(0);
+            parse_param(val, addr, nth, v)
+        };
+        // parameters that are written to can't be in immediate mode
+        let target = |nth: u32| match param(nth)? {
+            Param::Immediate(_) => Err(IcmError::InvalidWriteParam(addr)),
+            p => Ok(p),
+        };
+
+        let instr = match opcode {
+            1 => Instr::Add(param(0)?, param(1)?, target(2)?),
+            2 => Instr::Mul(param(0)?, param(1)?, target(2)?),
+            3 => Instr::Store(target(0)?),
+            4 => Instr::Show(param(0)?),
+            5 => Instr::JmpT(param(0)?, param(1)?),
+            6 => Instr::JmpF(param(0)?, param(1)?),
+            7 => Instr::CmpLt(param(0)?, param(1)?, target(2)?),
+            8 => Instr::CmpEq(param(0)?, param(1)?, target(2)?),
+            9 => Instr::AdjustBase(param(0)?),
+            99 => Instr::Halt,
+            _ => Instr::Unknown,
+        };
+        Ok(instr)
+    }
+
+    /// Parses an Intcode program separated by commas, whitespace or both.
+    pub fn parse_program(program: &str) -> Result<Vec<Word>, ParseIntError> {
+        program
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse::<Word>())
+            .collect()
+    }
+
+    /// Parses an Intcode program separated by `sep`. Whitespace around the
+    /// numbers and empty fields are ignored.
+    pub fn from_str_with_sep(program: &str, sep: char) -> Result<Vec<Word>, ParseIntError> {
+        program
+            .split(sep)
+            .map(|n| n.trim())
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse::<Word>())
+            .collect()
     }
 
     #[derive(Debug)]
-    enum Instr {
+    pub enum Instr {
         Unknown,
         Halt,
         Add(Param, Param, Param),
@@ -35,217 +316,4941 @@ pub mod icm {
         JmpF(Param, Param),
         CmpLt(Param, Param, Param),
         CmpEq(Param, Param, Param),
+        AdjustBase(Param),
+    }
+
+    impl Instr {
+        /// Number of memory words the instruction occupies.
+        // an instruction occupies at least one word, it is never empty
+        #[allow(clippy::len_without_is_empty)]
+        pub fn len(&self) -> usize {
+            match self {
+                Instr::Add(..) | Instr::Mul(..) | Instr::CmpLt(..) | Instr::CmpEq(..) => 4,
+                Instr::JmpT(..) | Instr::JmpF(..) => 3,
+                Instr::Store(_) | Instr::Show(_) | Instr::AdjustBase(_) => 2,
+                Instr::Halt | Instr::Unknown => 1,
+            }
+        }
+
+        fn params(&self) -> Vec<&Param> {
+            match self {
+                Instr::Add(p0, p1, p2)
+                | Instr::Mul(p0, p1, p2)
+                | Instr::CmpLt(p0, p1, p2)
+                | Instr::CmpEq(p0, p1, p2) => vec![p0, p1, p2],
+                Instr::JmpT(p0, p1) | Instr::JmpF(p0, p1) => vec![p0, p1],
+                Instr::Store(p0) | Instr::Show(p0) | Instr::AdjustBase(p0) => vec![p0],
+                Instr::Halt | Instr::Unknown => vec![],
+            }
+        }
+
+        fn opcode(&self) -> Option<Word> {
+            match self {
+                Instr::Add(..) => Some(1),
+                Instr::Mul(..) => Some(2),
+                Instr::Store(_) => Some(3),
+                Instr::Show(_) => Some(4),
+                Instr::JmpT(..) => Some(5),
+                Instr::JmpF(..) => Some(6),
+                Instr::CmpLt(..) => Some(7),
+                Instr::CmpEq(..) => Some(8),
+                Instr::AdjustBase(_) => Some(9),
+                Instr::Halt => Some(99),
+                Instr::Unknown => None,
+            }
+        }
+    }
+
+    /// Renders an instruction like `add @4, #3 -> @0`.
+    impl fmt::Display for Instr {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let name = self.opcode().and_then(opcode_name).unwrap_or("unknown");
+            match self {
+                Instr::Unknown | Instr::Halt => write!(f, "{}", name),
+                Instr::Add(p0, p1, p2)
+                | Instr::Mul(p0, p1, p2)
+                | Instr::CmpLt(p0, p1, p2)
+                | Instr::CmpEq(p0, p1, p2) => write!(f, "{} {}, {} -> {}", name, p0, p1, p2),
+                Instr::Store(p0) => write!(f, "{} -> {}", name, p0),
+                Instr::Show(p0) | Instr::AdjustBase(p0) => write!(f, "{} {}", name, p0),
+                Instr::JmpT(p0, p1) | Instr::JmpF(p0, p1) => write!(f, "{} {}, {}", name, p0, p1),
+            }
+        }
+    }
+
+    /// Mnemonic of an opcode, as used by `disassemble` and the `Display`
+    /// impl of `Instr`. Returns `None` for opcodes the machine doesn't know.
+    pub fn opcode_name(opcode: Word) -> Option<&'static str> {
+        match opcode {
+            1 => Some("add"),
+            2 => Some("mul"),
+            3 => Some("in"),
+            4 => Some("out"),
+            5 => Some("jt"),
+            6 => Some("jf"),
+            7 => Some("lt"),
+            8 => Some("eq"),
+            9 => Some("arb"),
+            99 => Some("halt"),
+            _ => None,
+        }
+    }
+
+    /// A source of input values for a processor.
+    pub trait IntcodeInput {
+        /// Returns the next value, or `None` if there is none yet, which
+        /// blocks the processor. Fails with `IcmError::RecvError` once no more
+        /// values will arrive, which stops the processor like a closed input
+        /// channel.
+        fn read(&mut self) -> Result<Option<Word>, IcmError>;
+    }
+
+    /// A destination for output values of a processor. Fails with
+    /// `IcmError::SendError` once nothing receives the values anymore, which
+    /// stops the processor like a closed output channel.
+    pub trait IntcodeOutput {
+        fn write(&mut self, v: Word) -> Result<(), IcmError>;
+    }
+
+    /// Waits for the next value. Fails once all senders are gone.
+    impl IntcodeInput for Receiver<Word> {
+        fn read(&mut self) -> Result<Option<Word>, IcmError> {
+            self.recv().map(Some).map_err(|_| IcmError::RecvError)
+        }
+    }
+
+    /// Takes values from the front, blocks when empty.
+    impl IntcodeInput for VecDeque<Word> {
+        fn read(&mut self) -> Result<Option<Word>, IcmError> {
+            Ok(self.pop_front())
+        }
+    }
+
+    /// Takes the values of a `Vec`, e.g. `vec![5, 0].into_iter()`, blocks
+    /// once they are used up.
+    impl IntcodeInput for std::vec::IntoIter<Word> {
+        fn read(&mut self) -> Result<Option<Word>, IcmError> {
+            Ok(self.next())
+        }
+    }
+
+    /// Fails once the receiver is gone.
+    impl IntcodeOutput for Sender<Word> {
+        fn write(&mut self, v: Word) -> Result<(), IcmError> {
+            self.send(v).map_err(|_| IcmError::SendError)
+        }
+    }
+
+    impl IntcodeOutput for VecDeque<Word> {
+        fn write(&mut self, v: Word) -> Result<(), IcmError> {
+            self.push_back(v);
+            Ok(())
+        }
+    }
+
+    impl IntcodeOutput for Vec<Word> {
+        fn write(&mut self, v: Word) -> Result<(), IcmError> {
+            self.push(v);
+            Ok(())
+        }
+    }
+
+    /// Receiving half of the input channel of a processor.
+    enum InputChannel {
+        Channel(Receiver<Word>),
+        Custom(Box<dyn IntcodeInput + Send>),
+    }
+
+    /// Sending half of the output channel of a processor.
+    enum OutputChannel {
+        Unbounded(Sender<Word>),
+        Bounded(SyncSender<Word>),
+        Custom(Box<dyn IntcodeOutput + Send>),
+    }
+
+    impl OutputChannel {
+        fn send(&mut self, v: Word) -> Result<(), SendError<Word>> {
+            match self {
+                OutputChannel::Unbounded(output) => output.send(v),
+                OutputChannel::Bounded(output) => output.send(v),
+                OutputChannel::Custom(output) => output.write(v).map_err(|_| SendError(v)),
+            }
+        }
+    }
+
+    /*
+    Configures a processor. Everything is optional: the defaults are an empty
+    program starting at IP 0 with a relative base of 0, and queued I/O instead
+    of channels.
+     */
+    #[derive(Default)]
+    pub struct ProcessorBuilder {
+        ip: usize,
+        relative_base: Word,
+        mem: Vec<Word>,
+        input: Option<Receiver<Word>>,
+        output: Option<Sender<Word>>,
+        trace: Option<TraceFn>,
+        nonblocking: bool,
+        max_instructions: Option<u64>,
+        max_memory: Option<usize>,
+        lenient: bool,
+        checked_arithmetic: bool,
+        output_bound: Option<usize>,
+        sparse: bool,
+        shared_program: Option<Arc<Vec<Word>>>,
+    }
+
+    impl ProcessorBuilder {
+        pub fn new() -> ProcessorBuilder {
+            ProcessorBuilder::default()
+        }
+
+        pub fn program(mut self, mem: Vec<Word>) -> ProcessorBuilder {
+            self.mem = mem;
+            self
+        }
+
+        pub fn input(mut self, input: Receiver<Word>) -> ProcessorBuilder {
+            self.input = Some(input);
+            self
+        }
+
+        pub fn output(mut self, output: Sender<Word>) -> ProcessorBuilder {
+            self.output = Some(output);
+            self
+        }
+
+        pub fn ip(mut self, ip: usize) -> ProcessorBuilder {
+            self.ip = ip;
+            self
+        }
+
+        pub fn relative_base(mut self, relative_base: Word) -> ProcessorBuilder {
+            self.relative_base = relative_base;
+            self
+        }
+
+        pub fn trace(mut self, f: TraceFn) -> ProcessorBuilder {
+            self.trace = Some(f);
+            self
+        }
+
+        pub fn nonblocking(mut self, nonblocking: bool) -> ProcessorBuilder {
+            self.nonblocking = nonblocking;
+            self
+        }
+
+        /*
+        Sends output on a channel that holds at most `bound` values, so the
+        processor blocks until they are received. The receiving end is taken
+        with `Processor::take_output_receiver`. Replaces the output channel.
+         */
+        pub fn output_bound(mut self, bound: usize) -> ProcessorBuilder {
+            self.output_bound = Some(bound);
+            self
+        }
+
+        /// Processors are strict by default, see `Processor::set_strict`.
+        pub fn strict(mut self, strict: bool) -> ProcessorBuilder {
+            self.lenient = !strict;
+            self
+        }
+
+        /// See `Processor::set_checked_arithmetic`.
+        pub fn checked_arithmetic(mut self, checked: bool) -> ProcessorBuilder {
+            self.checked_arithmetic = checked;
+            self
+        }
+
+        pub fn max_instructions(mut self, max: u64) -> ProcessorBuilder {
+            self.max_instructions = Some(max);
+            self
+        }
+
+        /// Sets the number of memory words a program can grow its memory to,
+        /// instead of `DEFAULT_MAX_MEMORY`.
+        pub fn max_memory(mut self, max: usize) -> ProcessorBuilder {
+            self.max_memory = Some(max);
+            self
+        }
+
+        /// See `Processor::is_sparse`.
+        pub fn sparse(mut self, sparse: bool) -> ProcessorBuilder {
+            self.sparse = sparse;
+            self
+        }
+
+        pub fn build(self) -> Processor {
+            let (output, output_receiver) = match self.output_bound {
+                Some(bound) => {
+                    let (send, recv) = sync_channel(bound);
+                    (Some(OutputChannel::Bounded(send)), Some(recv))
+                }
+                None => (self.output.map(OutputChannel::Unbounded), None),
+            };
+            let initial_mem = match self.shared_program {
+                Some(program) => program,
+                None => Arc::new(self.mem.clone()),
+            };
+            Processor {
+                ip: self.ip,
+                relative_base: self.relative_base,
+                initial_mem,
+                mem: self.mem,
+                sparse: if self.sparse {
+                    Some(HashMap::new())
+                } else {
+                    None
+                },
+                input: self.input.map(InputChannel::Channel),
+                output,
+                output_receiver,
+                input_queue: VecDeque::new(),
+                output_queue: VecDeque::new(),
+                trace: self.trace,
+                trace_filter: None,
+                trace_fast_ops: true,
+                trace_file: None,
+                code_write_hook: None,
+                uninit_read_hook: None,
+                written_high: HashSet::new(),
+                input_fn: None,
+                empty_input_value: None,
+                run_outputs: Vec::new(),
+                nonblocking: self.nonblocking,
+                strict: !self.lenient,
+                checked_arithmetic: self.checked_arithmetic,
+                halted: false,
+                blocked_on_input: false,
+                breakpoints: HashSet::new(),
+                watchpoints: HashSet::new(),
+                watch_hit: None,
+                instr_count: 0,
+                max_instructions: self.max_instructions,
+                max_memory: Some(self.max_memory.unwrap_or(DEFAULT_MAX_MEMORY)),
+                recv_timeout: None,
+                count_opcodes: false,
+                log_io: false,
+                input_log: Vec::new(),
+                output_log: Vec::new(),
+                opcode_histogram: HashMap::new(),
+                opcode_costs: HashMap::new(),
+                total_cost: 0,
+                opcode_handlers: HashMap::new(),
+            }
+        }
     }
 
     pub struct Processor {
         ip: usize,
-        mem: Vec<i32>,
-        input: Receiver<i32>,
-        output: Sender<i32>,
+        relative_base: Word,
+        mem: Vec<Word>,
+        sparse: Option<HashMap<usize, Word>>,
+        initial_mem: Arc<Vec<Word>>,
+        input: Option<InputChannel>,
+        output: Option<OutputChannel>,
+        output_receiver: Option<Receiver<Word>>,
+        input_queue: VecDeque<Word>,
+        output_queue: VecDeque<Word>,
+        trace: Option<TraceFn>,
+        trace_filter: Option<HashSet<Word>>,
+        trace_fast_ops: bool,
+        trace_file: Option<io::BufWriter<fs::File>>,
+        code_write_hook: Option<CodeWriteFn>,
+        uninit_read_hook: Option<UninitReadFn>,
+        written_high: HashSet<usize>,
+        input_fn: Option<InputFn>,
+        empty_input_value: Option<Word>,
+        run_outputs: Vec<Word>,
+        nonblocking: bool,
+        strict: bool,
+        checked_arithmetic: bool,
+        halted: bool,
+        blocked_on_input: bool,
+        breakpoints: HashSet<usize>,
+        watchpoints: HashSet<usize>,
+        watch_hit: Option<(usize, Word, Word)>,
+        instr_count: u64,
+        max_instructions: Option<u64>,
+        max_memory: Option<usize>,
+        recv_timeout: Option<Duration>,
+        count_opcodes: bool,
+        log_io: bool,
+        input_log: Vec<Word>,
+        output_log: Vec<Word>,
+        opcode_histogram: HashMap<Word, u64>,
+        opcode_costs: HashMap<Word, u64>,
+        total_cost: u64,
+        opcode_handlers: HashMap<Word, OpcodeFn>,
     }
 
     impl Processor {
         pub fn new(
             ip: usize,
-            mem: Vec<i32>,
-            input: Receiver<i32>,
-            output: Sender<i32>,
+            mem: Vec<Word>,
+            input: Receiver<Word>,
+            output: Sender<Word>,
         ) -> Processor {
-            Processor {
-                ip,
-                mem,
-                input,
-                output,
-            }
+            ProcessorBuilder::new()
+                .ip(ip)
+                .program(mem)
+                .input(input)
+                .output(output)
+                .build()
+        }
+
+        /// Creates a processor without I/O channels. Input is taken from a queue
+        /// filled with `push_input`, output is collected until `drain_output`.
+        /// An input instruction on an empty queue blocks the machine.
+        pub fn buffered(mem: Vec<Word>) -> Processor {
+            ProcessorBuilder::new().program(mem).build()
+        }
+
+        /*
+        Creates a buffered processor running a program shared with other
+        processors. Only the working memory is copied, `reset` copies from the
+        shared program again. To brute-force many runs in parallel, parse the
+        program once, wrap it in an `Arc` and create a processor per task:
+
+            let program = Arc::new(parse_program(&input)?);
+            let runs = (0..100).map(|noun| {
+                let program = Arc::clone(&program);
+                thread::spawn(move || {
+                    let mut p = Processor::with_shared_program(program);
+                    p.set_mem(1, noun);
+                    p.run()
+                })
+            });
+         */
+        pub fn with_shared_program(program: Arc<Vec<Word>>) -> Processor {
+            let mut builder = ProcessorBuilder::new().program(program.to_vec());
+            builder.shared_program = Some(program);
+            builder.build()
+        }
+
+        /// The program `reset` restores, for sharing with other processors
+        /// through `with_shared_program`.
+        pub fn shared_program(&self) -> Arc<Vec<Word>> {
+            Arc::clone(&self.initial_mem)
+        }
+
+        /// Creates a buffered processor with a fixed list of inputs. Once they are
+        /// exhausted, an input instruction blocks the machine.
+        pub fn with_inputs(program: Vec<Word>, inputs: Vec<Word>) -> Processor {
+            let mut p = Processor::buffered(program);
+            p.input_queue.extend(inputs);
+            p
+        }
+
+        /// Creates a processor with a new input channel and returns the sending
+        /// end of it, for feeding input after construction or from another
+        /// thread.
+        pub fn with_input_sender(
+            mem: Vec<Word>,
+            output: Sender<Word>,
+        ) -> (Processor, Sender<Word>) {
+            let (send_in, recv_in) = channel();
+            (Processor::new(0, mem, recv_in, output), send_in)
+        }
+
+        /*
+        Creates a processor that does I/O through custom sources, e.g. a
+        `VecDeque` for input and a `Vec` shared with the caller for output.
+        When the input source is empty, the machine blocks like a buffered one.
+         */
+        pub fn with_io(
+            mem: Vec<Word>,
+            input: Box<dyn IntcodeInput + Send>,
+            output: Box<dyn IntcodeOutput + Send>,
+        ) -> Processor {
+            let mut p = Processor::buffered(mem);
+            p.input = Some(InputChannel::Custom(input));
+            p.output = Some(OutputChannel::Custom(output));
+            p
+        }
+
+        pub fn builder() -> ProcessorBuilder {
+            ProcessorBuilder::new()
+        }
+
+        /// Creates a copy of this processor's machine state that uses new I/O
+        /// channels. Queued I/O, callbacks and breakpoints aren't copied.
+        pub fn fork_with_io(&self, input: Receiver<Word>, output: Sender<Word>) -> Processor {
+            let mut builder = ProcessorBuilder::new()
+                .program(self.mem.clone())
+                .ip(self.ip)
+                .relative_base(self.relative_base)
+                .input(input)
+                .output(output);
+            builder.shared_program = Some(Arc::clone(&self.initial_mem));
+            let mut p = builder.build();
+            p.sparse = self.sparse.clone();
+            p
+        }
+
+        /// Creates a processor from a comma-separated program like `"1,0,0,3,99"`.
+        /// Surrounding whitespace and empty fields are ignored.
+        pub fn from_str(
+            program: &str,
+            input: Receiver<Word>,
+            output: Sender<Word>,
+        ) -> Result<Processor, ParseIntError> {
+            let mem = parse_program(program)?;
+            Ok(Processor::new(0, mem, input, output))
+        }
+
+        /// Creates a processor from a program file. Parse errors are reported
+        /// as `io::ErrorKind::InvalidData`.
+        pub fn from_file<P: AsRef<Path>>(
+            path: P,
+            input: Receiver<Word>,
+            output: Sender<Word>,
+        ) -> io::Result<Processor> {
+            let program = fs::read_to_string(path)?;
+            Processor::from_str(&program, input, output)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+
+        pub fn get_ip(&self) -> usize {
+            self.ip
         }
 
+        /// Moves the IP. A halted machine resumes from there on the next run.
         pub fn set_ip(&mut self, ip: usize) {
             self.ip = ip;
+            self.halted = false;
+            self.blocked_on_input = false;
+        }
+
+        pub fn get_relative_base(&self) -> Word {
+            self.relative_base
+        }
+
+        pub fn set_relative_base(&mut self, base: Word) {
+            self.relative_base = base;
         }
 
-        pub fn set_memory(&mut self, mem: Vec<i32>) {
+        /// Loads a new program. It also becomes the state restored by `reset`.
+        pub fn set_memory(&mut self, mem: Vec<Word>) {
+            self.initial_mem = Arc::new(mem.clone());
             self.mem = mem;
+            if let Some(sparse) = self.sparse.as_mut() {
+                sparse.clear();
+            }
+            self.written_high.clear();
+            self.halted = false;
+            self.blocked_on_input = false;
         }
 
-        pub fn set_input(&mut self, input: Receiver<i32>) {
-            self.input = input;
+        /// Restores the initial program and rewinds the IP and relative base.
+        /// Memory is copied into the existing allocation.
+        pub fn reset(&mut self) {
+            self.mem.clear();
+            self.mem.extend_from_slice(&self.initial_mem);
+            if let Some(sparse) = self.sparse.as_mut() {
+                sparse.clear();
+            }
+            self.ip = 0;
+            self.halted = false;
+            self.blocked_on_input = false;
+            self.relative_base = 0;
+            self.instr_count = 0;
+            self.total_cost = 0;
+            self.opcode_histogram.clear();
+            self.written_high.clear();
+            self.input_log.clear();
+            self.output_log.clear();
         }
 
-        pub fn get_input(&self) -> &Receiver<i32> {
-            return &self.input;
+        /// Returns the value at `addr`, or `None` if memory doesn't reach that far.
+        /// With sparse memory, cells past the program exist once written.
+        pub fn get_mem(&self, addr: usize) -> Option<Word> {
+            match (self.mem.get(addr), &self.sparse) {
+                (None, Some(sparse)) => sparse.get(&addr).copied(),
+                (v, _) => v.copied(),
+            }
         }
 
-        pub fn set_output(&mut self, output: Sender<i32>) {
-            self.output = output;
+        /// Writes `value` to `addr`, growing memory if necessary. Panics
+        /// beyond the memory cap, like writing through `IndexMut`.
+        pub fn set_mem(&mut self, addr: usize, value: Word) {
+            if let Err(e) = self.ensure_addr(addr) {
+                panic!("{}", e);
+            }
+            self.store(addr, value);
         }
 
-        pub fn run(&mut self) {
-            loop {
-                if !self.run_instr() {
-                    break;
-                }
+        /// Writes each `(addr, value)` pair with `set_mem`. Patches are
+        /// applied in order, so a later patch for the same address wins.
+        pub fn apply_patches(&mut self, patches: &[(usize, Word)]) {
+            for &(addr, value) in patches {
+                self.set_mem(addr, value);
             }
         }
 
-        fn run_instr(&mut self) -> bool {
-            let i = self.fetch_instruction();
-            // println!("INSTR: {:?}", i);
-            match i {
-                Instr::Unknown => {
-                    println!("PANIC: Invalid instruction");
-                    return false;
-                }
-                Instr::Halt => {
-                    // println!("HALT");
-                    return false;
-                }
-                Instr::Add(p0, p1, p2) => {
-                    let p0 = self.fetch_param(p0);
-                    let p1 = self.fetch_param(p1);
-                    let p2 = match p2 {
-                        Param::Position(n) => n,
-                        _ => panic!("Invalid parameter"),
-                    };
-                    self.mem[p2] = p0 + p1;
-                    self.ip += 4;
-                }
-                Instr::Mul(p0, p1, p2) => {
-                    let p0 = self.fetch_param(p0);
-                    let p1 = self.fetch_param(p1);
-                    let p2 = match p2 {
-                        Param::Position(n) => n,
-                        _ => panic!("Invalid parameter"),
-                    };
-                    self.mem[p2] = p0 * p1;
-                    self.ip += 4;
+        /// Dense part of memory. With sparse memory, that is the program,
+        /// cells written beyond it aren't included.
+        pub fn memory(&self) -> &[Word] {
+            &self.mem
+        }
+
+        /*
+        Whether memory beyond the program is stored sparsely, selected with
+        `ProcessorBuilder::sparse`. Written cells are kept in a map instead of
+        growing memory up to the highest address, which saves memory for
+        programs writing to a few far away addresses. Cells never written read
+        as zero either way. The memory cap limits the number of cells stored,
+        so a program can write to any address.
+         */
+        pub fn is_sparse(&self) -> bool {
+            self.sparse.is_some()
+        }
+
+        /// Length of the program the processor was loaded with.
+        pub fn program_len(&self) -> usize {
+            self.initial_mem.len()
+        }
+
+        /// Current size of memory, which grows beyond `program_len` when the
+        /// program writes past its end.
+        /// With sparse memory, this is one past the highest written address.
+        pub fn memory_len(&self) -> usize {
+            let high = self.sparse_addrs().max().map_or(0, |addr| addr + 1);
+            self.mem.len().max(high)
+        }
+
+        /// Lists every address whose value differs between the two processors
+        /// as `(addr, own value, other value)`. Missing cells count as zero.
+        pub fn mem_diff(&self, other: &Processor) -> Vec<(usize, Word, Word)> {
+            let len = self.mem.len().max(other.mem.len());
+            let mut high: Vec<usize> = self
+                .sparse_addrs()
+                .chain(other.sparse_addrs())
+                .filter(|&addr| addr >= len)
+                .collect();
+            high.sort_unstable();
+            high.dedup();
+            (0..len)
+                .chain(high)
+                .map(|addr| (addr, self.read(addr), other.read(addr)))
+                .filter(|(_, a, b)| a != b)
+                .collect()
+        }
+
+        /// Addresses of the cells stored sparsely, in no particular order.
+        fn sparse_addrs(&self) -> impl Iterator<Item = usize> + '_ {
+            self.sparse.iter().flat_map(|sparse| sparse.keys().copied())
+        }
+
+        pub fn set_input(&mut self, input: Receiver<Word>) {
+            self.input = Some(InputChannel::Channel(input));
+        }
+
+        /// Returns the input channel, unless there is none or the processor
+        /// reads from a custom source.
+        pub fn get_input(&self) -> Option<&Receiver<Word>> {
+            match &self.input {
+                Some(InputChannel::Channel(input)) => Some(input),
+                _ => None,
+            }
+        }
+
+        pub fn set_output(&mut self, output: Sender<Word>) {
+            self.output = Some(OutputChannel::Unbounded(output));
+        }
+
+        /// Drops the output channel, so its receiver sees a disconnect once
+        /// no other sender is left. Later output is collected like that of a
+        /// buffered processor.
+        pub fn close_output(&mut self) {
+            self.output = None;
+        }
+
+        /// Drops the input and output channels, queues `inputs` and runs the
+        /// processor like a buffered one, e.g. to replay a machine built with
+        /// channels deterministically. Returns the output of the run, which
+        /// ends early if the inputs run out or an error occurs.
+        pub fn rebind_buffered(&mut self, inputs: Vec<Word>) -> Vec<Word> {
+            self.input = None;
+            self.input_fn = None;
+            self.output = None;
+            self.input_queue = inputs.into();
+            self.run();
+            self.drain_output()
+        }
+
+        /// Takes the receiving end of the bounded output channel created by
+        /// `ProcessorBuilder::output_bound`.
+        pub fn take_output_receiver(&mut self) -> Option<Receiver<Word>> {
+            self.output_receiver.take()
+        }
+
+        /// Queues a value for the input instructions. Queued values are
+        /// consumed before anything is read from the input channel.
+        pub fn push_input(&mut self, v: Word) {
+            self.input_queue.push_back(v);
+        }
+
+        /// Queues several values for the input instructions, see `push_input`.
+        /// Lets a caller seed a processor deterministically before it runs,
+        /// e.g. with a phase setting followed by an initial signal.
+        pub fn queue_input(&mut self, values: &[Word]) {
+            self.input_queue.extend(values);
+        }
+
+        /// Returns the values output since the last call. Only collects output
+        /// of processors without an output channel.
+        pub fn drain_output(&mut self) -> Vec<Word> {
+            self.output_queue.drain(..).collect()
+        }
+
+        /// Same as `drain_output`.
+        pub fn take_output(&mut self) -> Vec<Word> {
+            self.drain_output()
+        }
+
+        /// Queues the bytes of `line` followed by a newline as input.
+        pub fn send_ascii(&mut self, line: &str) {
+            for b in line.bytes() {
+                self.push_input(Word::from(b));
+            }
+            self.push_input(Word::from(b'\n'));
+        }
+
+        /// Takes all collected ASCII output as a string. Values outside the
+        /// ASCII range are left in the output for `drain_output`.
+        pub fn recv_ascii(&mut self) -> String {
+            let mut text = String::new();
+            let mut rest = VecDeque::new();
+            for v in self.output_queue.drain(..) {
+                if (0..128).contains(&v) {
+                    text.push(v as u8 as char);
+                } else {
+                    rest.push_back(v);
                 }
-                Instr::Store(p0) => {
-                    let p0 = match p0 {
-                        Param::Position(n) => n,
-                        _ => panic!("Invalid parameter"),
-                    };
-                    let input = self.input.recv().expect("Processor recv() error");
-                    self.mem[p0] = input;
-                    self.ip += 2;
+            }
+            self.output_queue = rest;
+            text
+        }
+
+        /*
+        Sends a line of ASCII input and runs until the machine asks for more
+        input or halts, e.g. to play a text adventure. Returns the ASCII text
+        output in the meantime, other output is left for `drain_output`. The
+        machine doesn't wait on the input channel while running.
+         */
+        pub fn interact(&mut self, command: &str) -> Result<String, IcmError> {
+            self.send_ascii(command);
+            let output = self.output.take();
+            let nonblocking = self.nonblocking;
+            self.nonblocking = true;
+            let state = self.execute(false);
+            self.output = output;
+            self.nonblocking = nonblocking;
+            match state {
+                RunState::AwaitingInput | RunState::Halted => Ok(self.recv_ascii()),
+                RunState::Error(e) => Err(e),
+                _ => Err(IcmError::RecvError),
+            }
+        }
+
+        /// Turns the processor into an iterator over its output. The machine is
+        /// run lazily until it produces the next value; the iterator ends when
+        /// it halts, blocks on input or fails. The output channel is detached.
+        pub fn outputs(mut self) -> impl Iterator<Item = Word> {
+            self.output = None;
+            std::iter::from_fn(move || loop {
+                if let Some(v) = self.output_queue.pop_front() {
+                    return Some(v);
                 }
-                Instr::Show(p0) => {
-                    let p0 = self.fetch_param(p0);
-                    self.output
-                        .send(p0)
-                        .expect("Processor output channel send error.");
-                    self.ip += 2;
+                match self.step() {
+                    Ok(StepResult::Continued) => {}
+                    _ => return None,
                 }
-                Instr::JmpT(p0, p1) => {
-                    let p0 = self.fetch_param(p0);
-                    if p0 == 0 {
-                        self.ip += 3;
-                    } else {
-                        let p1 = self.fetch_param(p1);
-                        self.ip = p1.try_into().unwrap();
+            })
+        }
+
+        /// Decodes the instruction at the IP without executing it. Anything that
+        /// doesn't decode, including an IP outside of memory, yields `Unknown`.
+        pub fn peek_instruction(&self) -> Instr {
+            self.decode(self.ip).unwrap_or(Instr::Unknown)
+        }
+
+        /// Returns the mode digits of the three parameters of the instruction
+        /// word at the IP, e.g. `[0, 1, 2]` for `21002`. The digits aren't
+        /// validated. The sign of a negative word is ignored.
+        pub fn current_modes(&self) -> [u8; 3] {
+            let pcode = self.read(self.ip).unsigned_abs() / 100;
+            [pcode % 10, pcode / 10 % 10, pcode / 100 % 10].map(|m| m as u8)
+        }
+
+        /*
+        Returns a listing of the program in memory, one instruction per line.
+        Parameters are prefixed by their mode: '@' position, '#' immediate and
+        '~' relative. Words that don't decode are listed as DATA.
+         */
+        pub fn disassemble(&self) -> String {
+            let mut listing = String::new();
+            let mut addr = 0;
+            while addr < self.mem.len() {
+                let instr = self.decode(addr).unwrap_or(Instr::Unknown);
+                let decoded = instr
+                    .opcode()
+                    .and_then(opcode_name)
+                    .map(|name| (name.to_uppercase(), instr.params()));
+                match decoded {
+                    // instructions running past the end of memory are data
+                    Some((name, params)) if addr + instr.len() <= self.mem.len() => {
+                        let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                        if params.is_empty() {
+                            listing.push_str(&format!("{:>5}: {}\n", addr, name));
+                        } else {
+                            let params = params.join(", ");
+                            listing.push_str(&format!("{:>5}: {} {}\n", addr, name, params));
+                        }
+                        addr += instr.len();
                     }
-                }
-                Instr::JmpF(p0, p1) => {
-                    let p0 = self.fetch_param(p0);
-                    if p0 == 0 {
-                        let p1 = self.fetch_param(p1);
-                        self.ip = p1.try_into().unwrap();
-                    } else {
-                        self.ip += 3;
+                    _ => {
+                        listing.push_str(&format!("{:>5}: DATA {}\n", addr, self.mem[addr]));
+                        addr += 1;
                     }
                 }
-                Instr::CmpLt(p0, p1, p2) => {
-                    let p0 = self.fetch_param(p0);
-                    let p1 = self.fetch_param(p1);
-                    let p2 = match p2 {
-                        Param::Position(n) => n,
-                        _ => panic!("Invalid parameter"),
-                    };
-                    if p0 < p1 {
-                        self.mem[p2] = 1;
+            }
+            listing
+        }
+
+        /*
+        Renders `len` words of memory from `start` on, 8 per line, each line
+        prefixed with the address of its first word. Values are right-aligned
+        in decimal, the word at the IP is put in brackets. The dump stops at
+        the end of memory.
+         */
+        pub fn dump_memory(&self, start: usize, len: usize) -> String {
+            let end = start.saturating_add(len).min(self.mem.len());
+            let cells = &self.mem[start.min(end)..end];
+            let width = cells.iter().map(|v| v.to_string().len()).max().unwrap_or(1);
+            let mut dump = String::new();
+            for (row, chunk) in cells.chunks(8).enumerate() {
+                let addr = start + row * 8;
+                let mut line = format!("{:>5}:", addr);
+                for (i, v) in chunk.iter().enumerate() {
+                    if addr + i == self.ip {
+                        line.push_str(&format!("[{:>w$}]", v, w = width));
                     } else {
-                        self.mem[p2] = 0;
+                        line.push_str(&format!(" {:>w$} ", v, w = width));
                     }
-                    self.ip += 4;
                 }
-                Instr::CmpEq(p0, p1, p2) => {
-                    let p0 = self.fetch_param(p0);
-                    let p1 = self.fetch_param(p1);
-                    let p2 = match p2 {
-                        Param::Position(n) => n,
-                        _ => panic!("Invalid parameter"),
-                    };
-                    if p0 == p1 {
-                        self.mem[p2] = 1;
-                    } else {
-                        self.mem[p2] = 0;
+                dump.push_str(line.trim_end());
+                dump.push('\n');
+            }
+            dump
+        }
+
+        /*
+        Follows the control flow of the program from `entry` and returns the
+        addresses of all instructions found, in ascending order. Execution
+        falls through to the next instruction except after a halt. Jumps are
+        followed when their target is an immediate value, a jump whose
+        condition is an immediate value only goes one way. Jump targets that
+        are computed at runtime can't be followed, and words that don't
+        decode end a path.
+         */
+        pub fn reachable_instructions(&self, entry: usize) -> Vec<usize> {
+            let mut seen = HashSet::new();
+            let mut todo = vec![entry];
+            while let Some(addr) = todo.pop() {
+                let instr = match self.decode(addr) {
+                    Ok(Instr::Unknown) | Err(_) => continue,
+                    Ok(instr) => instr,
+                };
+                if !seen.insert(addr) {
+                    continue;
+                }
+                let next = addr + instr.len();
+                match &instr {
+                    Instr::Halt => {}
+                    Instr::JmpT(cond, target) | Instr::JmpF(cond, target) => {
+                        let jump_if = matches!(instr, Instr::JmpT(..));
+                        let taken = match cond {
+                            Param::Immediate(v) => Some((*v != 0) == jump_if),
+                            _ => None,
+                        };
+                        if taken != Some(true) {
+                            todo.push(next);
+                        }
+                        if taken != Some(false) {
+                            if let Param::Immediate(target) = target {
+                                if let Ok(target) = (*target).try_into() {
+                                    todo.push(target);
+                                }
+                            }
+                        }
+                    }
+                    _ => todo.push(next),
+                }
+            }
+            let mut addrs: Vec<usize> = seen.into_iter().collect();
+            addrs.sort_unstable();
+            addrs
+        }
+
+        /// Decodes the whole memory the way `disassemble` does and returns the
+        /// errors found, with their addresses. Nothing is executed. Data words
+        /// that don't decode as instructions are reported as well.
+        pub fn validate(&self) -> Vec<(usize, IcmError)> {
+            let mut errors = vec![];
+            let mut addr = 0;
+            while addr < self.mem.len() {
+                match self.decode(addr) {
+                    Ok(Instr::Unknown) => {
+                        errors.push((addr, IcmError::UnknownOpcode(self.mem[addr], addr)));
+                        addr += 1;
+                    }
+                    Ok(instr) => addr += instr.len(),
+                    Err(e) => {
+                        errors.push((addr, e));
+                        addr += 1;
+                    }
+                }
+            }
+            errors
+        }
+
+        /// Installs a callback that is invoked before each instruction executes.
+        pub fn set_trace(&mut self, f: TraceFn) {
+            self.trace = Some(f);
+        }
+
+        /// Limits the trace callback to instructions with one of `opcodes`.
+        /// Tracing only instructions the fast path doesn't handle, like I/O,
+        /// keeps the fast path enabled.
+        pub fn set_trace_filter(&mut self, opcodes: HashSet<Word>) {
+            self.trace_fast_ops = opcodes.iter().any(|op| matches!(op, 1 | 2 | 5 | 6 | 7 | 8));
+            self.trace_filter = Some(opcodes);
+        }
+
+        /*
+        Appends a line to the file at `path` for every instruction executed from
+        now on: IP, opcode, parameter modes, parameters and the effect, e.g.
+        `4 1 010 @20, #2, @20 -> @20 = 7`. The file is flushed when the machine
+        halts and when the processor is dropped. Write errors are ignored.
+         */
+        pub fn enable_trace_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            self.trace_file = Some(io::BufWriter::new(file));
+            Ok(())
+        }
+
+        /// Installs a callback that is invoked whenever the program writes to an
+        /// address at or below the current IP, i.e. modifies its own code.
+        pub fn set_code_write_hook(&mut self, f: CodeWriteFn) {
+            self.code_write_hook = Some(f);
+        }
+
+        /// Installs a callback that is invoked whenever an instruction reads an
+        /// address beyond the initial program that wasn't written before. Such
+        /// reads yield zero, which can hide bugs in the program.
+        pub fn set_uninitialized_read_hook(&mut self, f: UninitReadFn) {
+            self.uninit_read_hook = Some(f);
+        }
+
+        /// Installs a callback that is asked for the next input value whenever
+        /// the input queue is empty and the input channel has nothing pending,
+        /// e.g. to read commands from stdin one byte at a time. The input
+        /// channel is no longer waited on, even in blocking mode.
+        pub fn set_input_fn(&mut self, f: InputFn) {
+            self.input_fn = Some(f);
+        }
+
+        /// With `Some(v)`, an input instruction that would block reads `v`
+        /// instead, like a day 23 computer polling an empty packet queue.
+        /// Blocking reads from the input channel still wait for a value.
+        pub fn set_empty_input_value(&mut self, v: Option<Word>) {
+            self.empty_input_value = v;
+        }
+
+        /// In non-blocking mode an input instruction with no pending input
+        /// doesn't wait on the channel. Instead the step returns
+        /// `StepResult::Blocked` and the instruction is retried on the next step.
+        pub fn set_nonblocking(&mut self, nonblocking: bool) {
+            self.nonblocking = nonblocking;
+        }
+
+        /// A strict processor fails with `UnknownOpcode` on an instruction it
+        /// can't decode. Otherwise such an instruction halts the machine.
+        pub fn set_strict(&mut self, strict: bool) {
+            self.strict = strict;
+        }
+
+        /// With checked arithmetic, an addition or multiplication that
+        /// overflows fails with `ArithmeticOverflow` instead of wrapping around.
+        pub fn set_checked_arithmetic(&mut self, checked: bool) {
+            self.checked_arithmetic = checked;
+        }
+
+        pub fn save_state(&self) -> MachineState {
+            let mut sparse: Vec<(usize, Word)> = self
+                .sparse
+                .iter()
+                .flat_map(|sparse| sparse.iter().map(|(&addr, &v)| (addr, v)))
+                .collect();
+            sparse.sort_unstable();
+            MachineState {
+                ip: self.ip,
+                relative_base: self.relative_base,
+                mem: self.mem.clone(),
+                sparse,
+            }
+        }
+
+        /// Restores a snapshot taken by `save_state`. I/O is left untouched.
+        pub fn restore_state(&mut self, state: MachineState) {
+            self.ip = state.ip;
+            self.relative_base = state.relative_base;
+            self.mem = state.mem;
+            if let Some(sparse) = self.sparse.as_mut() {
+                sparse.clear();
+            }
+            for (addr, v) in state.sparse {
+                self.store(addr, v);
+            }
+            self.halted = false;
+            self.blocked_on_input = false;
+        }
+
+        /// Captures the IP, relative base and memory for `rollback`.
+        pub fn checkpoint(&self) -> Checkpoint {
+            Checkpoint {
+                ip: self.ip,
+                relative_base: self.relative_base,
+                mem: self.mem.clone(),
+                sparse: self.sparse.clone(),
+            }
+        }
+
+        /// Returns to a checkpoint. Memory is copied into the existing
+        /// allocation, so rolling back repeatedly doesn't allocate. I/O is left
+        /// untouched.
+        pub fn rollback(&mut self, cp: &Checkpoint) {
+            self.ip = cp.ip;
+            self.relative_base = cp.relative_base;
+            self.halted = false;
+            self.blocked_on_input = false;
+            self.mem.clear();
+            self.mem.extend_from_slice(&cp.mem);
+            self.sparse.clone_from(&cp.sparse);
+        }
+
+        pub fn add_breakpoint(&mut self, ip: usize) {
+            self.breakpoints.insert(ip);
+        }
+
+        pub fn remove_breakpoint(&mut self, ip: usize) {
+            self.breakpoints.remove(&ip);
+        }
+
+        /// Makes `run_until_break` stop after an instruction that changes the
+        /// value at `addr`. Writing the value the cell already holds doesn't
+        /// count as a change.
+        pub fn add_watchpoint(&mut self, addr: usize) {
+            self.watchpoints.insert(addr);
+        }
+
+        pub fn remove_watchpoint(&mut self, addr: usize) {
+            self.watchpoints.remove(&addr);
+        }
+
+        pub fn run(&mut self) -> RunState {
+            self.execute(false)
+        }
+
+        /// Runs like `run`, but stops before executing an instruction at a
+        /// breakpoint, or after one that changed a watched address. The
+        /// instruction at the current IP is always executed, so calling this
+        /// again resumes from the breakpoint.
+        pub fn run_until_break(&mut self) -> RunState {
+            self.execute(true)
+        }
+
+        /// Runs like `run`, but gives up waiting on the input channel after
+        /// `dur` and returns `RunState::Timeout`, so a deadlocked processor
+        /// can be detected instead of blocking forever.
+        pub fn run_with_timeout(&mut self, dur: Duration) -> RunState {
+            self.recv_timeout = Some(dur);
+            let state = self.execute(false);
+            self.recv_timeout = None;
+            state
+        }
+
+        /// Runs the program to halt and returns the last value it output, if
+        /// any. Output is collected internally instead of being sent on the
+        /// output channel. Waiting for input that doesn't arrive is reported
+        /// as `RecvError`.
+        pub fn run_to_last_output(&mut self) -> Result<Option<Word>, IcmError> {
+            let output = self.output.take();
+            let state = self.execute(false);
+            self.output = output;
+            match state {
+                RunState::Halted => {
+                    let last = self.output_queue.pop_back();
+                    self.output_queue.clear();
+                    Ok(last)
+                }
+                RunState::Error(e) => Err(e),
+                _ => Err(IcmError::RecvError),
+            }
+        }
+
+        /// Runs until `n` values were output and returns them. Output is
+        /// collected internally instead of being sent on the output channel,
+        /// values not taken by `drain_output` yet count towards `n`. If the
+        /// machine stops first, the state it stopped in is returned and the
+        /// values output so far are left for `drain_output`.
+        pub fn run_until_outputs(&mut self, n: usize) -> Result<Vec<Word>, RunState> {
+            let output = self.output.take();
+            self.run_outputs.clear();
+            let mut state = None;
+            while self.output_queue.len() < n && state.is_none() {
+                state = self.step_state();
+            }
+            self.output = output;
+            match state {
+                Some(state) => Err(state),
+                None => Ok(self.output_queue.drain(..n).collect()),
+            }
+        }
+
+        /// Runs like `run`, but passes every output value to `f` as soon as it
+        /// is produced instead of sending it on the output channel. Values
+        /// not taken by `drain_output` yet are passed first.
+        pub fn run_with_output_handler(&mut self, mut f: impl FnMut(Word)) -> RunState {
+            let output = self.output.take();
+            self.run_outputs.clear();
+            let state = loop {
+                while let Some(v) = self.output_queue.pop_front() {
+                    f(v);
+                }
+                if let Some(state) = self.step_state() {
+                    break state;
+                }
+            };
+            self.output = output;
+            state
+        }
+
+        /// Executes at most `n` instructions. Returns `RunState::Paused` if
+        /// the machine could go on, or the state it stopped in otherwise.
+        /// Breakpoints are ignored.
+        pub fn run_steps(&mut self, n: u64) -> RunState {
+            self.run_outputs.clear();
+            for _ in 0..n {
+                if let Some(state) = self.step_state() {
+                    return state;
+                }
+            }
+            RunState::Paused
+        }
+
+        fn execute(&mut self, breakpoints: bool) -> RunState {
+            self.run_outputs.clear();
+            loop {
+                if let Some(state) = self.step_state() {
+                    return state;
+                }
+                if let Some((addr, old, new)) = self.watch_hit.take() {
+                    if breakpoints {
+                        return RunState::Watchpoint { addr, old, new };
                     }
-                    self.ip += 4;
                 }
+                if breakpoints && self.breakpoints.contains(&self.ip) {
+                    return RunState::Breakpoint(self.ip);
+                }
+            }
+        }
+
+        /// Executes one instruction. Returns the state to report if the
+        /// machine can't continue.
+        fn step_state(&mut self) -> Option<RunState> {
+            match self.run_step() {
+                Ok(StepResult::Continued) => None,
+                Ok(StepResult::Halted) => Some(RunState::Halted),
+                Ok(StepResult::Blocked) => Some(RunState::AwaitingInput),
+                Err(IcmError::SendError) => Some(RunState::OutputClosed),
+                Err(IcmError::RecvTimeout) => Some(RunState::Timeout),
+                Err(IcmError::RecvError) => Some(RunState::InputClosed),
+                Err(e) => Some(RunState::Error(IcmError::Faulted {
+                    at: self.ip,
+                    error: Box::new(e),
+                    outputs: self.run_outputs.clone(),
+                })),
+            }
+        }
+
+        /// Number of instructions executed since creation or the last reset.
+        pub fn instruction_count(&self) -> u64 {
+            self.instr_count
+        }
+
+        /// Limits the number of instructions a processor executes. Once the
+        /// limit is reached, execution fails with `InstructionLimitExceeded`.
+        pub fn set_max_instructions(&mut self, max: Option<u64>) {
+            self.max_instructions = max;
+        }
+
+        /// Enables or disables counting how often each opcode executes.
+        pub fn set_opcode_stats(&mut self, enabled: bool) {
+            self.count_opcodes = enabled;
+        }
+
+        /// Limits the number of memory words a program can grow its memory to.
+        /// Writes beyond the limit fail with `AddressOutOfBounds`.
+        pub fn set_max_memory(&mut self, max: Option<usize>) {
+            self.max_memory = max;
+        }
+
+        /// Number of times each opcode executed while counting was enabled.
+        pub fn opcode_stats(&self) -> &HashMap<Word, u64> {
+            &self.opcode_histogram
+        }
+
+        /// Sets the cost `total_cost` adds up for each opcode. Opcodes missing
+        /// from `costs` cost 1.
+        pub fn set_opcode_costs(&mut self, costs: HashMap<Word, u64>) {
+            self.opcode_costs = costs;
+        }
+
+        /// Summed cost of the instructions executed since creation or the last
+        /// reset, see `set_opcode_costs`. Stops at `u64::MAX`.
+        pub fn total_cost(&self) -> u64 {
+            self.total_cost
+        }
+
+        /// Enables or disables recording every value read by an input and
+        /// written by an output instruction.
+        pub fn set_io_log(&mut self, enabled: bool) {
+            self.log_io = enabled;
+        }
+
+        /// Values read and output while recording was enabled, in order.
+        pub fn io_log(&self) -> (&[Word], &[Word]) {
+            (&self.input_log, &self.output_log)
+        }
+
+        /// Whether the machine executed a halt instruction. Running a halted
+        /// machine does nothing until it is reset or its IP is moved.
+        pub fn is_halted(&self) -> bool {
+            self.halted
+        }
+
+        /// Whether the last step found no input for an input instruction. The
+        /// flag is cleared once a step executes an instruction again.
+        pub fn is_blocked(&self) -> bool {
+            self.blocked_on_input
+        }
+
+        /// Executes exactly one instruction.
+        pub fn step(&mut self) -> Result<StepResult, IcmError> {
+            let res = self.run_step();
+            // output is only collected over a run, for `IcmError::Faulted`
+            self.run_outputs.clear();
+            res
+        }
+
+        /// Executes one instruction as part of a run, adding its output to
+        /// `run_outputs`.
+        fn run_step(&mut self) -> Result<StepResult, IcmError> {
+            // only a hit by this instruction is reported by `run_until_break`
+            self.watch_hit = None;
+            if self.halted {
+                return Ok(StepResult::Halted);
+            }
+            if let Some(max) = self.max_instructions {
+                if self.instr_count >= max {
+                    return Err(IcmError::InstructionLimitExceeded(max));
+                }
+            }
+            let opcode = self.read(self.ip) % 100;
+            // the trace file shows the state before and after the instruction
+            let traced = match self.trace_file {
+                Some(_) => self
+                    .decode(self.ip)
+                    .ok()
+                    .map(|i| (self.ip, self.relative_base, i)),
+                None => None,
+            };
+            let res = self.run_instr()?;
+            if let Some((ip, base, instr)) = traced {
+                if res != StepResult::Blocked {
+                    self.write_trace_line(ip, base, opcode, &instr);
+                }
+            }
+            if res == StepResult::Halted {
+                self.halted = true;
+                if let Some(file) = self.trace_file.as_mut() {
+                    let _ = file.flush();
+                }
+            }
+            self.blocked_on_input = res == StepResult::Blocked;
+            if res != StepResult::Blocked {
+                self.instr_count += 1;
+                let cost = if self.opcode_costs.is_empty() {
+                    1
+                } else {
+                    self.opcode_costs.get(&opcode).copied().unwrap_or(1)
+                };
+                self.total_cost = self.total_cost.saturating_add(cost);
+                if self.count_opcodes {
+                    *self.opcode_histogram.entry(opcode).or_insert(0) += 1;
+                }
+            }
+            Ok(res)
+        }
+
+        /// Writes the trace file line of an instruction that was executed at
+        /// `ip` with relative base `base`.
+        fn write_trace_line(&mut self, ip: usize, base: Word, opcode: Word, instr: &Instr) {
+            let target = |p: &Param| match *p {
+                Param::Position(n) => n as Word,
+                Param::Relative(n) => base.wrapping_add(n),
+                Param::Immediate(n) => n,
+            };
+            let effect = match instr {
+                Instr::Add(_, _, p)
+                | Instr::Mul(_, _, p)
+                | Instr::CmpLt(_, _, p)
+                | Instr::CmpEq(_, _, p)
+                | Instr::Store(p) => {
+                    let addr = target(p);
+                    let v = addr.try_into().map(|a| self.read(a)).unwrap_or(0);
+                    format!("@{} = {}", addr, v)
+                }
+                Instr::Show(_) => format!("out {}", self.run_outputs.last().copied().unwrap_or(0)),
+                Instr::JmpT(..) | Instr::JmpF(..) => format!("ip = {}", self.ip),
+                Instr::AdjustBase(_) => format!("base = {}", self.relative_base),
+                Instr::Halt | Instr::Unknown => "halt".to_string(),
+            };
+            let modes: String = instr
+                .params()
+                .iter()
+                .map(|p| match p {
+                    Param::Position(_) => '0',
+                    Param::Immediate(_) => '1',
+                    Param::Relative(_) => '2',
+                })
+                .collect();
+            let mut line = format!("{} {} {:0<3}", ip, opcode, modes);
+            let params: Vec<String> = instr.params().iter().map(|p| p.to_string()).collect();
+            if !params.is_empty() {
+                line.push(' ');
+                line.push_str(&params.join(", "));
             }
-            return true;
+            if let Some(file) = self.trace_file.as_mut() {
+                let _ = writeln!(file, "{} -> {}", line, effect);
+            }
+        }
+
+        /// Executes `handler` instead of the built-in instruction whenever the
+        /// instruction at the IP has `opcode`. This also works for opcodes the
+        /// machine doesn't know.
+        pub fn override_opcode(&mut self, opcode: Word, handler: OpcodeFn) {
+            self.opcode_handlers.insert(opcode, handler);
         }
 
-        fn fetch_instruction(&self) -> Instr {
-            if let Some(val) = self.mem.get(self.ip) {
-                let opcode = val % 100;
-                let pcode = val / 100;
+        fn run_instr(&mut self) -> Result<StepResult, IcmError> {
+            if !self.opcode_handlers.is_empty() {
+                let opcode = self.read(self.ip) % 100;
+                // the handler is taken out while it has access to the processor
+                if let Some(mut handler) = self.opcode_handlers.remove(&opcode) {
+                    handler(self);
+                    self.opcode_handlers.entry(opcode).or_insert(handler);
+                    return Ok(StepResult::Continued);
+                }
+            }
+            // the trace callback needs the decoded instruction, and checking
+            // for uninitialized reads needs the resolved parameters
+            let traced = self.trace.is_some() && self.trace_fast_ops;
+            if !traced && self.uninit_read_hook.is_none() && self.run_fast() {
+                return Ok(StepResult::Continued);
+            }
 
-                match opcode {
-                    1 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        let p1 = parse_param(1, pcode, self.mem[self.ip + 2]);
-                        let p2 = parse_param(2, pcode, self.mem[self.ip + 3]);
-                        Instr::Add(p0, p1, p2)
+            let i = match self.fetch_instruction() {
+                Err(IcmError::UnknownOpcode(..)) if !self.strict => Instr::Unknown,
+                i => i?,
+            };
+            let opcode = self.read(self.ip) % 100;
+            if let Some(trace) = self.trace.as_mut() {
+                if self
+                    .trace_filter
+                    .as_ref()
+                    .is_none_or(|f| f.contains(&opcode))
+                {
+                    trace(self.ip, &i);
+                }
+            }
+            let len = i.len();
+            match i {
+                Instr::Unknown if !self.strict => return Ok(StepResult::Halted),
+                Instr::Unknown => {
+                    return Err(IcmError::UnknownOpcode(self.read(self.ip), self.ip));
+                }
+                Instr::Halt => {
+                    // println!("HALT");
+                    return Ok(StepResult::Halted);
+                }
+                Instr::Add(p0, p1, p2) => {
+                    let p0 = self.fetch_param(p0)?;
+                    let p1 = self.fetch_param(p1)?;
+                    let p2 = self.write_addr(p2)?;
+                    let v = self.arith(p0, p1, Word::checked_add, Word::wrapping_add)?;
+                    self.write(p2, v)?;
+                    self.ip += len;
+                }
+                Instr::Mul(p0, p1, p2) => {
+                    let p0 = self.fetch_param(p0)?;
+                    let p1 = self.fetch_param(p1)?;
+                    let p2 = self.write_addr(p2)?;
+                    let v = self.arith(p0, p1, Word::checked_mul, Word::wrapping_mul)?;
+                    self.write(p2, v)?;
+                    self.ip += len;
+                }
+                Instr::Store(p0) => {
+                    let p0 = self.write_addr(p0)?;
+                    let input = match self.read_input()? {
+                        Some(v) => v,
+                        None => return Ok(StepResult::Blocked),
+                    };
+                    self.write(p0, input)?;
+                    if self.log_io {
+                        self.input_log.push(input);
                     }
-                    2 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        let p1 = parse_param(1, pcode, self.mem[self.ip + 2]);
-                        let p2 = parse_param(2, pcode, self.mem[self.ip + 3]);
-                        Instr::Mul(p0, p1, p2)
+                    self.ip += len;
+                }
+                Instr::Show(p0) => {
+                    let p0 = self.fetch_param(p0)?;
+                    match &mut self.output {
+                        Some(output) => output.send(p0).map_err(|_| IcmError::SendError)?,
+                        None => self.output_queue.push_back(p0),
                     }
-                    3 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        Instr::Store(p0)
+                    self.run_outputs.push(p0);
+                    if self.log_io {
+                        self.output_log.push(p0);
                     }
-                    4 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        Instr::Show(p0)
+                    self.ip += len;
+                }
+                Instr::JmpT(p0, p1) => {
+                    let p0 = self.fetch_param(p0)?;
+                    if p0 == 0 {
+                        self.ip += len;
+                    } else {
+                        let p1 = self.fetch_param(p1)?;
+                        self.ip = p1.try_into().map_err(|_| IcmError::InvalidJumpTarget(p1))?;
                     }
-                    5 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        let p1 = parse_param(1, pcode, self.mem[self.ip + 2]);
-                        Instr::JmpT(p0, p1)
+                }
+                Instr::JmpF(p0, p1) => {
+                    let p0 = self.fetch_param(p0)?;
+                    if p0 == 0 {
+                        let p1 = self.fetch_param(p1)?;
+                        self.ip = p1.try_into().map_err(|_| IcmError::InvalidJumpTarget(p1))?;
+                    } else {
+                        self.ip += len;
                     }
-                    6 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        let p1 = parse_param(1, pcode, self.mem[self.ip + 2]);
-                        Instr::JmpF(p0, p1)
+                }
+                Instr::CmpLt(p0, p1, p2) => {
+                    let p0 = self.fetch_param(p0)?;
+                    let p1 = self.fetch_param(p1)?;
+                    let p2 = self.write_addr(p2)?;
+                    if p0 < p1 {
+                        self.write(p2, 1)?;
+                    } else {
+                        self.write(p2, 0)?;
                     }
-                    7 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        let p1 = parse_param(1, pcode, self.mem[self.ip + 2]);
-                        let p2 = parse_param(2, pcode, self.mem[self.ip + 3]);
-                        Instr::CmpLt(p0, p1, p2)
+                    self.ip += len;
+                }
+                Instr::CmpEq(p0, p1, p2) => {
+                    let p0 = self.fetch_param(p0)?;
+                    let p1 = self.fetch_param(p1)?;
+                    let p2 = self.write_addr(p2)?;
+                    if p0 == p1 {
+                        self.write(p2, 1)?;
+                    } else {
+                        self.write(p2, 0)?;
                     }
-                    8 => {
-                        let p0 = parse_param(0, pcode, self.mem[self.ip + 1]);
-                        let p1 = parse_param(1, pcode, self.mem[self.ip + 2]);
-                        let p2 = parse_param(2, pcode, self.mem[self.ip + 3]);
-                        Instr::CmpEq(p0, p1, p2)
+                    self.ip += len;
+                }
+                Instr::AdjustBase(p0) => {
+                    let p0 = self.fetch_param(p0)?;
+                    self.relative_base = self.relative_base.wrapping_add(p0);
+                    self.ip += len;
+                }
+            }
+            Ok(StepResult::Continued)
+        }
+
+        /*
+        Fast path for the arithmetic, comparison and jump instructions, which
+        make up most executed instructions. Decodes the instruction word inline
+        instead of building an `Instr`. Returns false without side effects for
+        any other instruction and for anything unusual, which is then left to
+        the regular decoder.
+         */
+        fn run_fast(&mut self) -> bool {
+            let val = self.read(self.ip);
+            let opcode = val % 100;
+            if !matches!(opcode, 1 | 2 | 5 | 6 | 7 | 8) {
+                return false;
+            }
+            let p0 = self.fast_param(val / 100 % 10, self.read(self.ip + 1));
+            let p1 = self.fast_param(val / 1000 % 10, self.read(self.ip + 2));
+            let (p0, p1) = match (p0, p1) {
+                (Some(p0), Some(p1)) => (p0, p1),
+                _ => return false,
+            };
+            if opcode == 5 || opcode == 6 {
+                if (p0 != 0) == (opcode == 5) {
+                    match p1.try_into() {
+                        Ok(target) => self.ip = target,
+                        Err(_) => return false,
                     }
-                    99 => Instr::Halt,
-                    _ => Instr::Unknown,
+                } else {
+                    self.ip += 3;
+                }
+                return true;
+            }
+
+            let p2 = self.read(self.ip + 3);
+            let p2 = match val / 10000 % 10 {
+                0 => p2.try_into().ok(),
+                2 => self.relative_base.wrapping_add(p2).try_into().ok(),
+                _ => None,
+            };
+            let p2 = match p2 {
+                Some(p2) => p2,
+                None => return false,
+            };
+            let res = match opcode {
+                1 => self.arith(p0, p1, Word::checked_add, Word::wrapping_add),
+                2 => self.arith(p0, p1, Word::checked_mul, Word::wrapping_mul),
+                7 => Ok(Word::from(p0 < p1)),
+                _ => Ok(Word::from(p0 == p1)),
+            };
+            // an overflow is reported by the decoding path
+            let res = match res {
+                Ok(res) => res,
+                Err(_) => return false,
+            };
+            // a write beyond the memory cap fails in the decoding path
+            if self.write(p2, res).is_err() {
+                return false;
+            }
+            self.ip += 4;
+            true
+        }
+
+        /// Applies `checked` or `wrapping`, depending on whether checked
+        /// arithmetic is enabled.
+        fn arith(
+            &self,
+            a: Word,
+            b: Word,
+            checked: fn(Word, Word) -> Option<Word>,
+            wrapping: fn(Word, Word) -> Word,
+        ) -> Result<Word, IcmError> {
+            if self.checked_arithmetic {
+                checked(a, b).ok_or(IcmError::ArithmeticOverflow(self.ip))
+            } else {
+                Ok(wrapping(a, b))
+            }
+        }
+
+        /// Resolves a read parameter for `run_fast`.
+        fn fast_param(&self, mode: Word, val: Word) -> Option<Word> {
+            match mode {
+                0 => val.try_into().ok().map(|addr| self.read(addr)),
+                1 => Some(val),
+                2 => self
+                    .relative_base
+                    .wrapping_add(val)
+                    .try_into()
+                    .ok()
+                    .map(|addr| self.read(addr)),
+                _ => None,
+            }
+        }
+
+        fn fetch_instruction(&self) -> Result<Instr, IcmError> {
+            self.decode(self.ip)
+        }
+
+        /// Decodes the instruction at `addr` without executing it.
+        fn decode(&self, addr: usize) -> Result<Instr, IcmError> {
+            match &self.sparse {
+                Some(_) if !self.mem.is_empty() => decode_with(|addr| self.get_mem(addr), addr),
+                _ => decode_instruction(&self.mem, addr),
+            }
+        }
+
+        /// Takes the next input value. Returns `None` if the machine has to
+        /// block because no input is available.
+        fn read_input(&mut self) -> Result<Option<Word>, IcmError> {
+            if let Some(v) = self.input_queue.read()? {
+                return Ok(Some(v));
+            }
+            match self.recv_input()? {
+                None => match self.input_fn.as_mut() {
+                    Some(f) => Ok(Some(f())),
+                    None => Ok(self.empty_input_value),
+                },
+                v => Ok(v),
+            }
+        }
+
+        /// Takes the next value from the input channel, if there is one.
+        fn recv_input(&mut self) -> Result<Option<Word>, IcmError> {
+            let input = match &mut self.input {
+                Some(InputChannel::Channel(input)) => input,
+                Some(InputChannel::Custom(input)) => return input.read(),
+                None => return Ok(None),
+            };
+            // waiting would keep the input function from ever being asked
+            if self.nonblocking || self.input_fn.is_some() {
+                return match input.try_recv() {
+                    Ok(v) => Ok(Some(v)),
+                    Err(TryRecvError::Empty) => Ok(None),
+                    Err(TryRecvError::Disconnected) => Err(IcmError::RecvError),
+                };
+            }
+            match self.recv_timeout {
+                Some(dur) => match input.recv_timeout(dur) {
+                    Ok(v) => Ok(Some(v)),
+                    Err(RecvTimeoutError::Timeout) => Err(IcmError::RecvTimeout),
+                    Err(RecvTimeoutError::Disconnected) => Err(IcmError::RecvError),
+                },
+                None => input.read(),
+            }
+        }
+
+        /// Reads a memory cell. Cells beyond the end of memory read as zero.
+        fn read(&self, addr: usize) -> Word {
+            self.get_mem(addr).unwrap_or(0)
+        }
+
+        /// Writes a value to memory on behalf of the running program.
+        fn write(&mut self, addr: usize, value: Word) -> Result<(), IcmError> {
+            self.ensure_addr(addr)?;
+            if !self.watchpoints.is_empty() && self.watchpoints.contains(&addr) {
+                let old = self.read(addr);
+                if old != value {
+                    self.watch_hit = Some((addr, old, value));
+                }
+            }
+            if addr <= self.ip {
+                if let Some(hook) = self.code_write_hook.as_mut() {
+                    hook(addr, value);
                 }
+            }
+            self.store(addr, value);
+            Ok(())
+        }
+
+        /// Writes a cell, growing dense memory or adding a sparse cell if
+        /// `addr` is beyond the end of memory. Cells beyond the program are
+        /// remembered as written for the uninitialized read hook, which may
+        /// be installed later.
+        fn store(&mut self, addr: usize, value: Word) {
+            if addr >= self.initial_mem.len() {
+                self.written_high.insert(addr);
+            }
+            if let Some(cell) = self.mem.get_mut(addr) {
+                *cell = value;
+            } else if let Some(sparse) = self.sparse.as_mut() {
+                sparse.insert(addr, value);
             } else {
-                panic!("Invalid memory address");
+                let len = addr.checked_add(1).expect("address out of bounds");
+                self.mem.resize(len, 0);
+                self.mem[addr] = value;
             }
         }
 
-        fn fetch_param(&self, p: Param) -> i32 {
+        /// Grows memory with zeros so that `addr` is a valid index, unless that
+        /// exceeds the memory cap. Sparse memory doesn't grow, the cap limits
+        /// the number of cells it stores instead of the address.
+        fn ensure_addr(&mut self, addr: usize) -> Result<(), IcmError> {
+            if addr < self.mem.len() {
+                return Ok(());
+            }
+            let max = self.max_memory.unwrap_or(usize::MAX);
+            match &self.sparse {
+                Some(sparse) => {
+                    if !sparse.contains_key(&addr) && self.mem.len() + sparse.len() >= max {
+                        return Err(IcmError::AddressOutOfBounds(addr));
+                    }
+                }
+                None => {
+                    if addr >= max {
+                        return Err(IcmError::AddressOutOfBounds(addr));
+                    }
+                    self.mem.resize(addr + 1, 0);
+                }
+            }
+            Ok(())
+        }
+
+        fn fetch_param(&mut self, p: Param) -> Result<Word, IcmError> {
+            let addr = match p {
+                Param::Position(n) => n,
+                Param::Immediate(n) => return Ok(n),
+                Param::Relative(n) => self.relative_addr(n)?,
+            };
+            if let Some(hook) = self.uninit_read_hook.as_mut() {
+                if addr >= self.initial_mem.len() && !self.written_high.contains(&addr) {
+                    hook(addr);
+                }
+            }
+            Ok(self.read(addr))
+        }
+
+        /// Resolves the address a write parameter refers to.
+        fn write_addr(&self, p: Param) -> Result<usize, IcmError> {
             match p {
-                Param::Position(n) => self.mem[n],
-                Param::Immediate(n) => n,
+                Param::Position(n) => Ok(n),
+                Param::Relative(n) => self.relative_addr(n),
+                Param::Immediate(_) => Err(IcmError::InvalidWriteParam(self.ip)),
             }
         }
+
+        /// Resolves the address `offset` words from the relative base.
+        fn relative_addr(&self, offset: Word) -> Result<usize, IcmError> {
+            let addr = self.relative_base.wrapping_add(offset);
+            addr.try_into().map_err(|_| IcmError::NegativeAddress(addr))
+        }
     } // END IMPL Processor
+
+    /// Reads memory like a slice, panics beyond the end of memory. Sparse
+    /// memory reads as zero beyond the program instead.
+    impl Index<usize> for Processor {
+        type Output = Word;
+
+        fn index(&self, addr: usize) -> &Word {
+            match &self.sparse {
+                Some(sparse) if addr >= self.mem.len() => sparse.get(&addr).unwrap_or(&0),
+                _ => &self.mem[addr],
+            }
+        }
+    }
+
+    /// Writes memory, growing it if necessary. Panics beyond the memory cap,
+    /// like `set_mem`.
+    impl IndexMut<usize> for Processor {
+        fn index_mut(&mut self, addr: usize) -> &mut Word {
+            if let Err(e) = self.ensure_addr(addr) {
+                panic!("{}", e);
+            }
+            match self.sparse.as_mut() {
+                Some(sparse) if addr >= self.mem.len() => sparse.entry(addr).or_insert(0),
+                _ => &mut self.mem[addr],
+            }
+        }
+    }
+
+    /*
+    Processor with a fixed amount of memory that never allocates. It decodes
+    instructions like `Processor` and has the same semantics, but accessing an
+    address beyond `N` fails with `AddressOutOfBounds` instead of growing the
+    memory. Input and output are passed to every call of `step` and `run`.
+     */
+    pub struct FixedProcessor<const N: usize> {
+        ip: usize,
+        relative_base: Word,
+        mem: [Word; N],
+    }
+
+    impl<const N: usize> FixedProcessor<N> {
+        /// Loads `program` into memory, the rest is zeroed. Fails if the
+        /// program doesn't fit.
+        pub fn new(program: &[Word]) -> Result<FixedProcessor<N>, IcmError> {
+            if program.len() > N {
+                return Err(IcmError::AddressOutOfBounds(N));
+            }
+            let mut mem = [0; N];
+            mem[..program.len()].copy_from_slice(program);
+            Ok(FixedProcessor {
+                ip: 0,
+                relative_base: 0,
+                mem,
+            })
+        }
+
+        pub fn get_ip(&self) -> usize {
+            self.ip
+        }
+
+        pub fn memory(&self) -> &[Word] {
+            &self.mem
+        }
+
+        /// Runs until the machine halts, fails, or `input` has no value for
+        /// an input instruction.
+        pub fn run(
+            &mut self,
+            input: &mut dyn IntcodeInput,
+            output: &mut dyn IntcodeOutput,
+        ) -> RunState {
+            loop {
+                match self.step(input, output) {
+                    Ok(StepResult::Continued) => {}
+                    Ok(StepResult::Halted) => return RunState::Halted,
+                    Ok(StepResult::Blocked) => return RunState::AwaitingInput,
+                    Err(IcmError::SendError) => return RunState::OutputClosed,
+                    Err(IcmError::RecvError) => return RunState::InputClosed,
+                    Err(e) => return RunState::Error(e),
+                }
+            }
+        }
+
+        /// Executes exactly one instruction.
+        pub fn step(
+            &mut self,
+            input: &mut dyn IntcodeInput,
+            output: &mut dyn IntcodeOutput,
+        ) -> Result<StepResult, IcmError> {
+            let i = decode_instruction(&self.mem, self.ip)?;
+            let len = i.len();
+            match i {
+                Instr::Unknown => {
+                    return Err(IcmError::UnknownOpcode(self.mem[self.ip], self.ip));
+                }
+                Instr::Halt => return Ok(StepResult::Halted),
+                Instr::Add(p0, p1, p2) => {
+                    let v = self.value(p0)?.wrapping_add(self.value(p1)?);
+                    self.mem[self.addr(p2)?] = v;
+                    self.ip += len;
+                }
+                Instr::Mul(p0, p1, p2) => {
+                    let v = self.value(p0)?.wrapping_mul(self.value(p1)?);
+                    self.mem[self.addr(p2)?] = v;
+                    self.ip += len;
+                }
+                Instr::Store(p0) => {
+                    let addr = self.addr(p0)?;
+                    match input.read()? {
+                        Some(v) => self.mem[addr] = v,
+                        None => return Ok(StepResult::Blocked),
+                    }
+                    self.ip += len;
+                }
+                Instr::Show(p0) => {
+                    output.write(self.value(p0)?)?;
+                    self.ip += len;
+                }
+                Instr::JmpT(p0, p1) => {
+                    let taken = self.value(p0)? != 0;
+                    self.jump(taken, p1, len)?;
+                }
+                Instr::JmpF(p0, p1) => {
+                    let taken = self.value(p0)? == 0;
+                    self.jump(taken, p1, len)?;
+                }
+                Instr::CmpLt(p0, p1, p2) => {
+                    let v = (self.value(p0)? < self.value(p1)?) as Word;
+                    self.mem[self.addr(p2)?] = v;
+                    self.ip += len;
+                }
+                Instr::CmpEq(p0, p1, p2) => {
+                    let v = (self.value(p0)? == self.value(p1)?) as Word;
+                    self.mem[self.addr(p2)?] = v;
+                    self.ip += len;
+                }
+                Instr::AdjustBase(p0) => {
+                    self.relative_base = self.relative_base.wrapping_add(self.value(p0)?);
+                    self.ip += len;
+                }
+            }
+            Ok(StepResult::Continued)
+        }
+
+        /// Moves the IP to `target` if the jump is taken, past the jump
+        /// instruction otherwise.
+        fn jump(&mut self, taken: bool, target: Param, len: usize) -> Result<(), IcmError> {
+            if taken {
+                let target = self.value(target)?;
+                self.ip = target
+                    .try_into()
+                    .map_err(|_| IcmError::InvalidJumpTarget(target))?;
+            } else {
+                self.ip += len;
+            }
+            Ok(())
+        }
+
+        /// Resolves a position or relative parameter to an address in memory.
+        fn addr(&self, p: Param) -> Result<usize, IcmError> {
+            let addr = match p {
+                Param::Position(n) => n,
+                Param::Relative(n) => {
+                    let addr = self.relative_base.wrapping_add(n);
+                    addr.try_into()
+                        .map_err(|_| IcmError::NegativeAddress(addr))?
+                }
+                Param::Immediate(_) => return Err(IcmError::InvalidWriteParam(self.ip)),
+            };
+            if addr < N {
+                Ok(addr)
+            } else {
+                Err(IcmError::AddressOutOfBounds(addr))
+            }
+        }
+
+        fn value(&self, p: Param) -> Result<Word, IcmError> {
+            match p {
+                Param::Immediate(v) => Ok(v),
+                p => Ok(self.mem[self.addr(p)?]),
+            }
+        }
+    }
+
+    /*
+    Runs a program to completion on a fixed list of inputs.
+    program - Intcode program to run.
+    inputs  - Values for the input instructions, in order.
+    Returns all values the program output. Running out of inputs is reported
+    as `RecvError`.
+     */
+    pub fn execute(program: &[Word], inputs: &[Word]) -> Result<Vec<Word>, IcmError> {
+        run_collecting(Processor::with_inputs(program.to_vec(), inputs.to_vec()))
+    }
+
+    /*
+    Runs a program like `execute`, but stops it after a number of instructions.
+    program          - Intcode program to run.
+    inputs           - Values for the input instructions, in order.
+    max_instructions - Number of instructions after which the program fails
+                       with `InstructionLimitExceeded`, wrapped in `Faulted`
+                       like every error.
+    This never panics or hangs, whatever the program, so it can be used to
+    fuzz the interpreter.
+     */
+    pub fn execute_limited(
+        program: &[Word],
+        inputs: &[Word],
+        max_instructions: u64,
+    ) -> Result<Vec<Word>, IcmError> {
+        let mut p = Processor::with_inputs(program.to_vec(), inputs.to_vec());
+        p.set_max_instructions(Some(max_instructions));
+        run_collecting(p)
+    }
+
+    /// Runs a buffered processor to halt and returns its output.
+    fn run_collecting(mut p: Processor) -> Result<Vec<Word>, IcmError> {
+        match p.run() {
+            RunState::Halted => Ok(p.drain_output()),
+            RunState::Error(e) => Err(e),
+            _ => Err(IcmError::RecvError),
+        }
+    }
+
+    #[test]
+    fn test_execute() {
+        // outputs 999, 1000 or 1001 for inputs below, equal to or above 8
+        let program = parse_program(
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+        )
+        .unwrap();
+        assert_eq!(Ok(vec![999]), execute(&program, &[7]));
+        assert_eq!(Ok(vec![1000]), execute(&program, &[8]));
+        assert_eq!(Ok(vec![1001]), execute(&program, &[9]));
+        assert_eq!(Err(IcmError::RecvError), execute(&program, &[]));
+        assert_eq!(Ok(vec![1, 2]), execute(&[104, 1, 104, 2, 99], &[]));
+    }
+
+    #[test]
+    fn test_execute_limited() {
+        assert_eq!(
+            Ok(vec![1, 2]),
+            execute_limited(&[104, 1, 104, 2, 99], &[], 3)
+        );
+        // the output produced before the limit is kept
+        let fault = IcmError::Faulted {
+            at: 4,
+            error: Box::new(IcmError::InstructionLimitExceeded(2)),
+            outputs: vec![1, 2],
+        };
+        assert_eq!(Err(fault), execute_limited(&[104, 1, 104, 2, 99], &[], 2));
+        assert_eq!(
+            Err(IcmError::NegativeAddress(-1)),
+            execute_limited(&[204, -1, 99], &[], 10).map_err(|e| e.cause().clone())
+        );
+        assert_eq!(
+            Ok(vec![Word::MIN]),
+            execute_limited(&[1101, Word::MAX, 1, 7, 4, 7, 99], &[], 10)
+        );
+
+        // random programs terminate without panicking
+        let mut seed: u64 = 42;
+        for _ in 0..2000 {
+            let program: Vec<Word> = (0..20)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    let v = (seed >> 33) as Word;
+                    match v % 4 {
+                        0 => v % 10 + v % 3 * 100 + v % 7 * 1000 + v % 5 * 10000,
+                        1 => v % 30,
+                        2 => -(v % 30),
+                        _ => v << 20,
+                    }
+                })
+                .collect();
+            let _ = execute_limited(&program, &[1, 2, 3], 1000);
+        }
+    }
+
+    #[test]
+    fn test_fixed_processor() {
+        // day 9 quine, which needs memory beyond the program
+        let quine =
+            parse_program("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99").unwrap();
+        let mut p = FixedProcessor::<128>::new(&quine).unwrap();
+        let mut output = vec![];
+        assert_eq!(RunState::Halted, p.run(&mut VecDeque::new(), &mut output));
+        assert_eq!(quine, output);
+        assert_eq!(16, p.memory()[100]);
+
+        let mut p = FixedProcessor::<16>::new(&quine).unwrap();
+        assert_eq!(
+            Some(&IcmError::AddressOutOfBounds(100)),
+            p.run(&mut VecDeque::new(), &mut vec![]).error()
+        );
+        assert!(FixedProcessor::<8>::new(&quine).is_err());
+
+        // the same day 5 comparison as the growable processor
+        let program = parse_program("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+        let mut p = FixedProcessor::<11>::new(&program).unwrap();
+        let mut input: VecDeque<Word> = VecDeque::new();
+        let mut output = vec![];
+        assert_eq!(RunState::AwaitingInput, p.run(&mut input, &mut output));
+        assert_eq!(0, p.get_ip());
+        input.push_back(8);
+        assert_eq!(RunState::Halted, p.run(&mut input, &mut output));
+        assert_eq!(vec![1], output);
+
+        let mut p = FixedProcessor::<4>::new(&[1105, 1, -1]).unwrap();
+        assert_eq!(
+            Err(IcmError::InvalidJumpTarget(-1)),
+            p.step(&mut VecDeque::new(), &mut vec![])
+        );
+        let mut p = FixedProcessor::<4>::new(&[42]).unwrap();
+        assert_eq!(
+            Err(IcmError::UnknownOpcode(42, 0)),
+            p.step(&mut VecDeque::new(), &mut vec![])
+        );
+    }
+
+    #[test]
+    fn test_add_mul() {
+        use crate::test_util::assert_same_output;
+
+        // day 2 examples, checked by final memory
+        let examples = [
+            ("1,0,0,0,99", vec![2, 0, 0, 0, 99]),
+            ("2,3,0,3,99", vec![2, 3, 0, 6, 99]),
+            ("2,4,4,5,99,0", vec![2, 4, 4, 5, 99, 9801]),
+            ("1,1,1,4,99,5,6,0,99", vec![30, 1, 1, 4, 2, 5, 6, 0, 99]),
+            ("1002,4,3,4,33", vec![1002, 4, 3, 4, 99]),
+            ("1101,100,-1,4,0", vec![1101, 100, -1, 4, 99]),
+        ];
+        for (program, mem) in examples.iter() {
+            let mut p = Processor::buffered(parse_program(program).unwrap());
+            assert_eq!(RunState::Halted, p.run(), "{}", program);
+            assert_eq!(&mem[..], p.memory(), "{}", program);
+        }
+
+        // every combination of position and immediate mode for the inputs
+        assert_same_output(&[1, 7, 8, 7, 4, 7, 99, 12, -5], &[], &[7]);
+        assert_same_output(&[101, -5, 7, 7, 4, 7, 99, 12], &[], &[7]);
+        assert_same_output(&[1001, 7, -5, 7, 4, 7, 99, 12], &[], &[7]);
+        assert_same_output(&[1101, 12, -5, 7, 4, 7, 99, 0], &[], &[7]);
+        assert_same_output(&[2, 7, 8, 7, 4, 7, 99, 3, -4], &[], &[-12]);
+        assert_same_output(&[102, 3, 7, 7, 4, 7, 99, -4], &[], &[-12]);
+        assert_same_output(&[1002, 7, 3, 7, 4, 7, 99, -4], &[], &[-12]);
+        assert_same_output(&[1102, 3, -4, 7, 4, 7, 99, 0], &[], &[-12]);
+    }
+
+    #[test]
+    fn test_store_show() {
+        use crate::test_util::assert_same_output;
+
+        assert_same_output(&[3, 0, 4, 0, 99], &[42], &[42]);
+        assert_same_output(&[3, 0, 4, 0, 99], &[-42], &[-42]);
+        assert_same_output(&[3, 7, 104, -7, 4, 7, 99, 0], &[13], &[-7, 13]);
+        // the stored value is what later reads of the cell see
+        assert_same_output(&[3, 12, 3, 13, 1, 12, 13, 14, 4, 14, 99], &[4, 5], &[9]);
+        let mut p = Processor::with_inputs(vec![3, 9, 3, 10, 99], vec![4, 5]);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(&[3, 9, 3, 10, 99, 0, 0, 0, 0, 4, 5], p.memory());
+    }
+
+    #[test]
+    fn test_show_modes() {
+        use crate::test_util::assert_same_output;
+
+        // position mode outputs the cell, immediate mode the parameter itself
+        assert_same_output(&[4, 3, 99, 42], &[], &[42]);
+        assert_same_output(&[104, 3, 99, 42], &[], &[3]);
+        assert_same_output(&[104, -3, 99], &[], &[-3]);
+        assert_same_output(&[109, 2, 204, 3, 99, 42], &[], &[42]);
+        assert_same_output(&[4, 0, 104, 0, 99], &[], &[4, 0]);
+
+        let mut p = Processor::buffered(vec![4, 3, 99, 42, 7]);
+        p.set_relative_base(1);
+        assert_eq!(Ok(42), p.fetch_param(Param::Position(3)));
+        assert_eq!(Ok(3), p.fetch_param(Param::Immediate(3)));
+        assert_eq!(Ok(7), p.fetch_param(Param::Relative(3)));
+        assert_eq!(Ok(0), p.fetch_param(Param::Position(100)));
+        assert_eq!(
+            Err(IcmError::NegativeAddress(-1)),
+            p.fetch_param(Param::Relative(-2))
+        );
+    }
+
+    #[test]
+    fn test_jumps() {
+        use crate::test_util::assert_same_output;
+
+        // jump if true skips the output only for a nonzero condition
+        assert_same_output(&[1105, 0, 5, 104, 1, 99], &[], &[1]);
+        assert_same_output(&[1105, 7, 5, 104, 1, 99], &[], &[]);
+        assert_same_output(&[1105, -1, 5, 104, 1, 99], &[], &[]);
+        // jump if false skips it only for zero
+        assert_same_output(&[1106, 0, 5, 104, 1, 99], &[], &[]);
+        assert_same_output(&[1106, 7, 5, 104, 1, 99], &[], &[1]);
+        // condition and target in position mode
+        assert_same_output(&[5, 7, 8, 104, 1, 99, 0, 1, 5], &[], &[]);
+        assert_same_output(&[6, 7, 8, 104, 1, 99, 0, 1, 5], &[], &[1]);
+
+        // day 5 examples: output 0 if the input was zero, 1 otherwise
+        for program in [
+            "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9",
+            "3,3,1105,-1,9,1101,0,0,12,4,12,99,1",
+        ]
+        .iter()
+        {
+            let program = parse_program(program).unwrap();
+            assert_same_output(&program, &[0], &[0]);
+            assert_same_output(&program, &[5], &[1]);
+            assert_same_output(&program, &[-5], &[1]);
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        use crate::test_util::assert_same_output;
+
+        // the result overwrites the parameter of the output instruction
+        assert_same_output(&[1107, 1, 2, 5, 104, -1, 99], &[], &[1]);
+        assert_same_output(&[1107, 2, 2, 5, 104, -1, 99], &[], &[0]);
+        assert_same_output(&[1107, -3, -2, 5, 104, -1, 99], &[], &[1]);
+        assert_same_output(&[1108, 2, 2, 5, 104, -1, 99], &[], &[1]);
+        assert_same_output(&[1108, 2, 3, 5, 104, -1, 99], &[], &[0]);
+
+        // day 5 examples comparing the input to 8
+        let equal_pos = parse_program("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+        let less_pos = parse_program("3,9,7,9,10,9,4,9,99,-1,8").unwrap();
+        let equal_imm = parse_program("3,3,1108,-1,8,3,4,3,99").unwrap();
+        let less_imm = parse_program("3,3,1107,-1,8,3,4,3,99").unwrap();
+        let cmp = parse_program(
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+        )
+        .unwrap();
+        for input in -2..12 {
+            let equal = (input == 8) as Word;
+            let less = (input < 8) as Word;
+            assert_same_output(&equal_pos, &[input], &[equal]);
+            assert_same_output(&less_pos, &[input], &[less]);
+            assert_same_output(&equal_imm, &[input], &[equal]);
+            assert_same_output(&less_imm, &[input], &[less]);
+            assert_same_output(&cmp, &[input], &[1000 + (input - 8).signum()]);
+        }
+    }
+
+    #[test]
+    fn test_relative_mode() {
+        use std::sync::mpsc::channel;
+
+        // add ~1, ~2 -> ~-3 with a relative base of 4
+        let mem = vec![22201, 1, 2, -3, 99, 6, 7];
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::new(0, mem, recv_in, send_out);
+        p.relative_base = 4;
+        assert_eq!(RunState::Halted, p.run());
+
+        assert_eq!(vec![22201, 13, 2, -3, 99, 6, 7], p.mem);
+    }
+
+    #[test]
+    fn test_adjust_base() {
+        use std::sync::mpsc::channel;
+
+        // arb #12, arb #-3, out ~1, halt
+        let mem = vec![109, 12, 109, -3, 204, 1, 99, 0, 0, 0, 42];
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::new(0, mem, recv_in, send_out);
+        assert_eq!(RunState::Halted, p.run());
+
+        assert_eq!(9, p.relative_base);
+        assert_eq!(Ok(42), recv_out.recv());
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        use std::sync::mpsc::channel;
+
+        let mem = vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0];
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::new(0, mem, recv_in, send_out);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Ok(1219070632396864), recv_out.recv());
+
+        let mem = vec![104, 1125899906842624, 99];
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::new(0, mem, recv_in, send_out);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Ok(1125899906842624), recv_out.recv());
+    }
+
+    #[test]
+    fn test_memory_expansion() {
+        use std::sync::mpsc::channel;
+
+        // add #7, #35 -> @10000, out @10000, out @20000, halt
+        let mem = vec![1101, 7, 35, 10000, 4, 10000, 4, 20000, 99];
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::new(0, mem, recv_in, send_out);
+        assert_eq!(RunState::Halted, p.run());
+
+        assert_eq!(10001, p.mem.len());
+        assert_eq!(42, p.mem[10000]);
+        assert_eq!(Ok(42), recv_out.recv());
+        assert_eq!(Ok(0), recv_out.recv());
+    }
+
+    #[test]
+    fn test_program_len() {
+        let mut p = Processor::buffered(parse_program("1101,1,2,100,99").unwrap());
+        assert_eq!(5, p.program_len());
+        assert_eq!(5, p.memory_len());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(5, p.program_len());
+        assert_eq!(101, p.memory_len());
+        p.reset();
+        assert_eq!(5, p.memory_len());
+    }
+
+    #[test]
+    fn test_sparse_memory() {
+        use crate::test_util::run_lockstep;
+
+        // stores 15 at 100000 and 3 at 250000 via the relative base, outputs their sum
+        let program = parse_program(
+            "1101,7,8,100000,109,250000,21101,1,2,0,1,100000,250000,99999,4,99999,99",
+        )
+        .unwrap();
+        let mut dense = Processor::buffered(program.clone());
+        let mut p = Processor::builder().program(program).sparse(true).build();
+        assert!(p.is_sparse());
+        assert!(!dense.is_sparse());
+        assert_eq!(Ok(()), run_lockstep(&mut dense, &mut p));
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(17, p.memory().len());
+        assert_eq!(250001, p.memory_len());
+        assert_eq!(Some(15), p.get_mem(100000));
+        assert_eq!(None, p.get_mem(5000));
+        assert_eq!(0, p[5000]);
+        assert_eq!(18, p[99999]);
+
+        let cp = p.checkpoint();
+        let state = p.save_state();
+        assert_eq!(vec![(99999, 18), (100000, 15), (250000, 3)], state.sparse);
+        p.reset();
+        assert_eq!(17, p.memory_len());
+        p.rollback(&cp);
+        assert_eq!(Some(3), p.get_mem(250000));
+        p.reset();
+        p.restore_state(state);
+        assert_eq!(Some(3), p.get_mem(250000));
+        p[300000] = 5;
+        assert_eq!(300001, p.memory_len());
+        assert_eq!(17, p.memory().len());
+
+        // the memory cap limits the number of cells, not the address
+        let program = parse_program("1101,1,2,1000000000,1101,3,4,1000000000,1101,5,6,200,99");
+        let mut p = Processor::builder()
+            .program(program.unwrap())
+            .sparse(true)
+            .max_memory(14)
+            .build();
+        assert_eq!(Some(&IcmError::AddressOutOfBounds(200)), p.run().error());
+        assert_eq!(Some(7), p.get_mem(1000000000));
+
+        // beyond DEFAULT_MAX_MEMORY, where dense memory stops
+        let program = parse_program("1101,1,2,1000000000,99").unwrap();
+        let mut p = Processor::builder()
+            .program(program.clone())
+            .sparse(true)
+            .build();
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(3, p[1000000000]);
+        let mut p = Processor::buffered(program);
+        assert_eq!(
+            Some(&IcmError::AddressOutOfBounds(1000000000)),
+            p.run().error()
+        );
+    }
+
+    #[test]
+    fn test_max_memory() {
+        let program = parse_program("1101,1,2,99,1101,3,4,100,99").unwrap();
+        let mut p = Processor::builder()
+            .program(program.clone())
+            .max_memory(100)
+            .build();
+        assert_eq!(Some(&IcmError::AddressOutOfBounds(100)), p.run().error());
+        assert_eq!(4, p.get_ip());
+        assert_eq!(Some(3), p.get_mem(99));
+
+        let mut p = Processor::buffered(program);
+        p.set_max_memory(Some(50));
+        assert_eq!(Some(&IcmError::AddressOutOfBounds(99)), p.run().error());
+        p.set_max_memory(None);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(101, p.memory().len());
+
+        // untrusted programs can't allocate all of the address space
+        let mut p = Processor::buffered(parse_program("21101,1,1,-1,99").unwrap());
+        p.set_relative_base(Word::MAX);
+        let addr = Word::MAX as usize - 1;
+        assert_eq!(Some(&IcmError::AddressOutOfBounds(addr)), p.run().error());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_index_beyond_max_memory() {
+        let mut p = Processor::buffered(vec![99]);
+        p[100] = 1;
+        assert_eq!(101, p.memory_len());
+        p[usize::MAX] = 1;
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_set_mem_beyond_max_memory() {
+        let mut p = Processor::buffered(vec![99]);
+        p.set_max_memory(Some(10));
+        p.set_mem(9, 1);
+        assert_eq!(10, p.memory_len());
+        p.set_mem(10, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_set_mem_last_address() {
+        let mut p = Processor::buffered(vec![99]);
+        p.set_max_memory(None);
+        p.set_mem(usize::MAX, 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        use std::sync::mpsc::channel;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::new(0, vec![1, 0, 0, 0, 42], recv_in, send_out);
+        let fault = IcmError::Faulted {
+            at: 4,
+            error: Box::new(IcmError::UnknownOpcode(42, 4)),
+            outputs: vec![],
+        };
+        assert_eq!(RunState::Error(fault), p.run());
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::new(
+            0,
+            vec![1101, 1, 1, 0, 11101, 1, 1, 0, 99],
+            recv_in,
+            send_out,
+        );
+        assert_eq!(Some(&IcmError::InvalidWriteParam(4)), p.run().error());
+        assert_eq!(4, p.ip);
+        assert_eq!(2, p.mem[0]);
+
+        let (send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        drop(send_in);
+        let mut p = Processor::new(0, vec![3, 0, 99], recv_in, send_out);
+        assert_eq!(RunState::InputClosed, p.run());
+        assert_eq!(0, p.get_ip());
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        drop(recv_out);
+        let mut p = Processor::new(0, vec![104, 1, 99], recv_in, send_out);
+        assert_eq!(RunState::OutputClosed, p.run());
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::new(0, vec![1106, 0, 10], recv_in, send_out);
+        assert_eq!(Some(&IcmError::AddressOutOfBounds(10)), p.run().error());
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::new(0, vec![1105, 1, 3, -101], recv_in, send_out);
+        assert_eq!(
+            Some(&IcmError::InvalidInstruction(-101, 3)),
+            p.run().error()
+        );
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::new(0, vec![1101, -3, 1, 7, 5, 7, 7, 0, 99], recv_in, send_out);
+        assert_eq!(Some(&IcmError::InvalidJumpTarget(-2)), p.run().error());
+        assert_eq!(4, p.ip);
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let program = vec![1101, Word::MAX, 1, 9, 1102, Word::MAX, 2, 9, 99, 0];
+        let mut p = Processor::buffered(program.clone());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(-2, p[9]);
+
+        let mut p = Processor::builder()
+            .program(program)
+            .checked_arithmetic(true)
+            .build();
+        assert_eq!(Some(&IcmError::ArithmeticOverflow(0)), p.run().error());
+        assert_eq!(0, p[9]);
+        p.set_ip(4);
+        assert_eq!(Some(&IcmError::ArithmeticOverflow(4)), p.run().error());
+        p.set_checked_arithmetic(false);
+        assert_eq!(RunState::Halted, p.run());
+
+        // the largest day 9 product still fits
+        let mut p =
+            Processor::buffered(parse_program("1102,34915192,34915192,7,4,7,99,0").unwrap());
+        p.set_checked_arithmetic(true);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![1219070632396864], p.drain_output());
+    }
+
+    #[test]
+    fn test_empty_program() {
+        let mut p = Processor::buffered(vec![]);
+        assert_eq!(Err(IcmError::EmptyProgram), p.step());
+        assert_eq!(Some(&IcmError::EmptyProgram), p.run().error());
+        let fault = IcmError::Faulted {
+            at: 0,
+            error: Box::new(IcmError::EmptyProgram),
+            outputs: vec![],
+        };
+        assert_eq!(Err(fault), execute(&parse_program("").unwrap(), &[]));
+        assert_eq!("", p.disassemble());
+
+        let mut p = FixedProcessor::<0>::new(&[]).unwrap();
+        assert_eq!(
+            Some(&IcmError::EmptyProgram),
+            p.run(&mut VecDeque::new(), &mut vec![]).error()
+        );
+    }
+
+    #[test]
+    fn test_faulted() {
+        use std::sync::mpsc::channel;
+
+        let (send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::from_str("104,1,3,8,104,2,104,3,99", recv_in, send_out).unwrap();
+        p.set_nonblocking(true);
+        assert_eq!(RunState::AwaitingInput, p.run());
+
+        // only outputs of the run that failed are reported
+        send_in.send(42).unwrap();
+        let fault = IcmError::Faulted {
+            at: 8,
+            error: Box::new(IcmError::UnknownOpcode(42, 8)),
+            outputs: vec![2, 3],
+        };
+        assert_eq!(RunState::Error(fault), p.run());
+        assert_eq!(vec![1, 2, 3], recv_out.try_iter().collect::<Vec<Word>>());
+        assert_eq!(
+            "unknown opcode 42 at 8 after 2 outputs",
+            IcmError::Faulted {
+                at: 8,
+                error: Box::new(IcmError::UnknownOpcode(42, 8)),
+                outputs: vec![2, 3],
+            }
+            .to_string()
+        );
+
+        // single steps report the plain decoding error and collect nothing
+        assert_eq!(Err(IcmError::UnknownOpcode(42, 8)), p.step());
+        p.set_ip(4);
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert!(p.run_outputs.is_empty());
+    }
+
+    #[test]
+    fn test_strict() {
+        let program = parse_program("104,1,42,104,2,99").unwrap();
+        let mut p = Processor::builder().program(program.clone()).build();
+        let fault = IcmError::Faulted {
+            at: 2,
+            error: Box::new(IcmError::UnknownOpcode(42, 2)),
+            outputs: vec![1],
+        };
+        assert_eq!(RunState::Error(fault), p.run());
+
+        let mut p = Processor::builder().program(program).strict(false).build();
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(2, p.get_ip());
+        assert_eq!(vec![1], p.drain_output());
+
+        // an invalid parameter mode is garbage as well
+        let mut p = Processor::buffered(vec![104, 1, 301, 0, 0, 0, 99]);
+        p.set_strict(false);
+        assert_eq!(RunState::Halted, p.run());
+        p.set_strict(true);
+        p.set_ip(2);
+        assert_eq!(Err(IcmError::UnknownOpcode(301, 2)), p.step());
+    }
+
+    #[test]
+    fn test_from_str() {
+        use std::sync::mpsc::channel;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::from_str("1,9,10,3,2,3,11,0,99,30,40,50,\n", recv_in, send_out)
+            .expect("Could not parse program");
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(3500, p.mem[0]);
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        assert!(Processor::from_str("1,0,x,99", recv_in, send_out).is_err());
+    }
+
+    #[test]
+    fn test_parse_program() {
+        assert_eq!(Ok(vec![1, -2, 3, 99]), parse_program("1,-2,3,99"));
+        assert_eq!(Ok(vec![1, -2, 3, 99]), parse_program(" 1 -2\n3\t99\n"));
+        assert_eq!(Ok(vec![1, -2, 3, 99]), parse_program("1, -2,,3 ,99,"));
+        assert_eq!(Ok(vec![]), parse_program(""));
+        assert!(parse_program("1;2").is_err());
+
+        assert_eq!(
+            Ok(vec![1, -2, 3, 99]),
+            from_str_with_sep("1; -2;3 ;99;\n", ';')
+        );
+        assert!(from_str_with_sep("1,2", ';').is_err());
+    }
+
+    #[test]
+    fn test_from_file() {
+        use std::sync::mpsc::channel;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let p = Processor::from_file("input", recv_in, send_out).expect("Could not load input");
+        assert_eq!(3, p.mem[0]);
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let err = Processor::from_file("no_such_file", recv_in, send_out).err();
+        assert_eq!(Some(io::ErrorKind::NotFound), err.map(|e| e.kind()));
+    }
+
+    #[test]
+    fn test_memory_access() {
+        use std::sync::mpsc::channel;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::from_str("1,0,0,0,99,30,40,50", recv_in, send_out).unwrap();
+        p.set_mem(1, 5);
+        p.set_mem(2, 6);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Some(70), p.get_mem(0));
+        assert_eq!(None, p.get_mem(8));
+
+        p.set_mem(10, 1);
+        assert_eq!(&[70, 5, 6, 0, 99, 30, 40, 50, 0, 0, 1], p.memory());
+    }
+
+    #[test]
+    fn test_index() {
+        let mut p = Processor::buffered(parse_program("1,9,10,3,2,3,11,0,99,30,40,50").unwrap());
+        p[1] = 9;
+        p[2] = 11;
+        p[14] = 1;
+        assert_eq!(15, p.memory().len());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(4000, p[0]);
+        assert_eq!(1, p[14]);
+    }
+
+    #[test]
+    fn test_step() {
+        use std::sync::mpsc::channel;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::from_str("1101,2,3,0,1102,2,3,1,99", recv_in, send_out).unwrap();
+
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert_eq!(4, p.ip);
+        assert_eq!(Some(5), p.get_mem(0));
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert_eq!(Some(6), p.get_mem(1));
+        assert_eq!(Ok(StepResult::Halted), p.step());
+        assert_eq!(Ok(StepResult::Halted), p.step());
+        assert_eq!(8, p.ip);
+    }
+
+    #[test]
+    fn test_trace() {
+        use std::sync::mpsc::channel;
+        use std::sync::{Arc, Mutex};
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::from_str("1101,2,3,0,104,7,99", recv_in, send_out).unwrap();
+
+        let log = Arc::new(Mutex::new(vec![]));
+        let trace_log = Arc::clone(&log);
+        p.set_trace(Box::new(move |ip, i| {
+            trace_log.lock().unwrap().push(format!("{}: {:?}", ip, i));
+        }));
+        assert_eq!(RunState::Halted, p.run());
+
+        assert_eq!(
+            vec![
+                "0: Add(Immediate(2), Immediate(3), Position(0))",
+                "4: Show(Immediate(7))",
+                "6: Halt",
+            ],
+            *log.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        use std::sync::{Arc, Mutex};
+
+        let mut p =
+            Processor::with_inputs(parse_program("3,9,1001,9,1,9,4,9,99,0").unwrap(), vec![4]);
+        let log = Arc::new(Mutex::new(vec![]));
+        let trace_log = Arc::clone(&log);
+        p.set_trace(Box::new(move |ip, _| trace_log.lock().unwrap().push(ip)));
+        p.set_trace_filter([3, 4].iter().copied().collect());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![0, 6], *log.lock().unwrap());
+        assert_eq!(vec![5], p.drain_output());
+
+        log.lock().unwrap().clear();
+        p.reset();
+        p.push_input(4);
+        p.set_trace_filter([1, 99].iter().copied().collect());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![2, 8], *log.lock().unwrap());
+    }
+
+    #[test]
+    fn test_trace_file() {
+        let path = std::env::temp_dir().join(format!("icm-trace-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let program = "109,5,203,15,1001,20,2,20,1006,20,99,4,20,99";
+        let mut p = Processor::with_inputs(parse_program(program).unwrap(), vec![5]);
+        p.enable_trace_file(&path).unwrap();
+        assert_eq!(RunState::Halted, p.run());
+        let trace = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            vec![
+                "0 9 100 #5 -> base = 5",
+                "2 3 200 ~15 -> @20 = 5",
+                "4 1 010 @20, #2, @20 -> @20 = 7",
+                "8 6 010 @20, #99 -> ip = 11",
+                "11 4 000 @20 -> out 7",
+                "13 99 000 -> halt",
+            ],
+            trace.lines().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_nonblocking() {
+        use std::sync::mpsc::channel;
+
+        let (send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::from_str("3,9,1001,9,1,9,4,9,99,0", recv_in, send_out).unwrap();
+        p.set_nonblocking(true);
+
+        assert_eq!(Ok(StepResult::Blocked), p.step());
+        assert_eq!(0, p.ip);
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert_eq!(0, p.ip);
+
+        send_in.send(41).unwrap();
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Ok(42), recv_out.recv());
+        assert_eq!(Ok(StepResult::Halted), p.step());
+        // an empty channel blocks, a disconnected one won't deliver anymore
+        let (send_in, recv_in) = channel();
+        p.set_input(recv_in);
+        p.set_ip(0);
+        assert_eq!(RunState::AwaitingInput, p.run());
+        drop(send_in);
+        assert_eq!(Err(IcmError::RecvError), p.step());
+        assert_eq!(RunState::InputClosed, p.run());
+        assert_eq!(0, p.ip);
+    }
+
+    #[test]
+    fn test_is_blocked() {
+        let mut p = Processor::buffered(vec![3, 9, 4, 9, 3, 9, 99]);
+        assert!(!p.is_blocked());
+        assert_eq!(Ok(StepResult::Blocked), p.step());
+        assert!(p.is_blocked());
+
+        p.push_input(5);
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert!(!p.is_blocked());
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert!(p.is_blocked());
+
+        p.reset();
+        assert!(!p.is_blocked());
+        p.queue_input(&[1, 2]);
+        assert_eq!(RunState::Halted, p.run());
+        assert!(!p.is_blocked());
+    }
+
+    #[test]
+    fn test_store_retry() {
+        // stores the input in relative mode, then adds 10 to it
+        let mut p =
+            Processor::buffered(parse_program("109,10,203,3,1001,13,10,13,4,13,99").unwrap());
+        p.set_io_log(true);
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert_eq!(Ok(StepResult::Blocked), p.step());
+        assert_eq!(2, p.get_ip());
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert_eq!(2, p.get_ip());
+        assert_eq!(11, p.memory().len());
+
+        p.queue_input(&[5, 6]);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![15], p.drain_output());
+        assert_eq!(Some(15), p.get_mem(13));
+        assert_eq!((&[5][..], &[15][..]), p.io_log());
+        assert_eq!(5, p.instruction_count());
+    }
+
+    #[test]
+    fn test_buffered() {
+        // outputs 1 if the input is equal to 8, 0 otherwise
+        let mem = parse_program("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+        let mut p = Processor::buffered(mem.clone());
+        assert_eq!(Ok(StepResult::Blocked), p.step());
+        assert_eq!(0, p.ip);
+
+        p.push_input(8);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![1], p.drain_output());
+        assert_eq!(Vec::<Word>::new(), p.drain_output());
+
+        let mut p = Processor::buffered(mem);
+        p.push_input(7);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![0], p.drain_output());
+    }
+
+    #[test]
+    fn test_outputs() {
+        // quine from day 9
+        let mem =
+            parse_program("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99").unwrap();
+        let p = Processor::buffered(mem.clone());
+        assert_eq!(mem, p.outputs().collect::<Vec<Word>>());
+
+        let mut p = Processor::buffered(parse_program("104,72,104,105,3,0,104,33,99").unwrap());
+        p.push_input(0);
+        let s: String = p.outputs().map(|c| c as u8 as char).collect();
+        assert_eq!("Hi!", s);
+
+        let p = Processor::buffered(parse_program("104,1,3,0,104,2,99").unwrap());
+        assert_eq!(vec![1], p.outputs().collect::<Vec<Word>>());
+    }
+
+    #[test]
+    fn test_ascii() {
+        // echoes two input characters, then outputs a large number
+        let mem = parse_program("3,100,4,100,3,100,4,100,104,1000000,104,10,99").unwrap();
+        let mut p = Processor::buffered(mem);
+        p.send_ascii("A");
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!("A\n\n", p.recv_ascii());
+        assert_eq!(vec![1000000], p.drain_output());
+    }
+
+    #[test]
+    fn test_interact() {
+        // echoes every line after a "> " prompt, until it reads a '.'
+        let program =
+            "104,62,104,32,3,40,1008,40,46,41,1005,41,25,4,40,1008,40,10,41,1005,41,0,1105,1,4,99";
+        let mut p = Processor::buffered(parse_program(program).unwrap());
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert_eq!("> ", p.recv_ascii());
+        assert_eq!(Ok("north\n> ".to_string()), p.interact("north"));
+        assert_eq!(Ok("\n> ".to_string()), p.interact(""));
+        assert_eq!(Ok("".to_string()), p.interact("."));
+        assert!(p.is_halted());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mem = parse_program("1,0,0,0,109,5,99").unwrap();
+        let mut p = Processor::buffered(mem.clone());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Some(2), p.get_mem(0));
+        assert_eq!(5, p.relative_base);
+
+        p.reset();
+        assert_eq!(&mem[..], p.memory());
+        assert_eq!(0, p.ip);
+        assert_eq!(0, p.relative_base);
+
+        p.set_mem(1, 5);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Some(6), p.get_mem(0));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let p = Processor::buffered(parse_program("1002,4,3,4,33,3,0,204,-1,99,12345,1").unwrap());
+        let listing = [
+            "    0: MUL @4, #3, @4",
+            "    4: DATA 33",
+            "    5: IN @0",
+            "    7: OUT ~-1",
+            "    9: HALT",
+            "   10: DATA 12345",
+            "   11: DATA 1",
+        ];
+        assert_eq!(listing.join("\n") + "\n", p.disassemble());
+    }
+
+    #[test]
+    fn test_dump_memory() {
+        let mut p = Processor::buffered(
+            parse_program("1002,4,3,4,33,3,0,204,-1,99,12345,1,-100000").unwrap(),
+        );
+        p.set_ip(9);
+        let dump = [
+            "    0:    1002        4        3        4       33        3        0      204",
+            "    8:      -1 [     99]   12345        1  -100000",
+        ];
+        assert_eq!(dump.join("\n") + "\n", p.dump_memory(0, 100));
+        assert_eq!("   10: 12345      1\n", p.dump_memory(10, 2));
+        assert_eq!("", p.dump_memory(20, 8));
+    }
+
+    #[test]
+    fn test_disassemble_relative() {
+        // the day 9 quine, followed by mixed-mode instructions
+        let program =
+            "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99,21201,-3,7,4,209,0";
+        let p = Processor::buffered(parse_program(program).unwrap());
+        let listing = [
+            "    0: ARB #1",
+            "    2: OUT ~-1",
+            "    4: ADD @100, #1, @100",
+            "    8: EQ @100, #16, @101",
+            "   12: JF @101, #0",
+            "   15: HALT",
+            "   16: ADD ~-3, #7, ~4",
+            "   20: ARB ~0",
+        ];
+        assert_eq!(listing.join("\n") + "\n", p.disassemble());
+
+        let instrs: Vec<String> = [16, 20]
+            .iter()
+            .map(|&addr| p.decode(addr).unwrap().to_string())
+            .collect();
+        assert_eq!(vec!["add ~-3, #7 -> ~4", "arb ~0"], instrs);
+    }
+
+    #[test]
+    fn test_reachable_instructions() {
+        let program = crate::asm::assemble(
+            "jt #1, #5      # 0: skips the data
+             data 1, 2      # 3
+             in @20         # 5
+             jf @20, #15    # 7
+             out @20        # 10
+             jt @20, ~0     # 12: computed target
+             halt           # 15
+             data 42",
+        )
+        .unwrap();
+        let p = Processor::buffered(program);
+        assert_eq!(vec![0, 5, 7, 10, 12, 15], p.reachable_instructions(0));
+        assert_eq!(vec![10, 12, 15], p.reachable_instructions(10));
+        assert!(p.reachable_instructions(16).is_empty());
+        assert!(p.reachable_instructions(100).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let p = Processor::buffered(vec![1, 0, 0, 0, 99]);
+        assert_eq!(Vec::<(usize, IcmError)>::new(), p.validate());
+
+        let p = Processor::buffered(vec![1101, 1, 1, 0, 42, 11101, 1, 1, 0, 99, -5]);
+        assert_eq!(
+            vec![
+                (4, IcmError::UnknownOpcode(42, 4)),
+                (5, IcmError::InvalidWriteParam(5)),
+                (10, IcmError::InvalidInstruction(-5, 10)),
+            ],
+            p.validate()
+        );
+    }
+
+    #[test]
+    fn test_display_instr() {
+        let p = Processor::buffered(parse_program("1002,4,3,0,203,-2,1105,0,99,99").unwrap());
+        let listing: Vec<String> = [0, 4, 6, 9, 7]
+            .iter()
+            .map(|&addr| p.decode(addr).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "mul @4, #3 -> @0",
+                "in -> ~-2",
+                "jt #0, #99",
+                "halt",
+                "unknown"
+            ],
+            listing
+        );
+        assert_eq!("~7", Param::Relative(7).to_string());
+    }
+
+    #[test]
+    fn test_apply_patches() {
+        let mut p = Processor::buffered(parse_program("1,0,0,0,99").unwrap());
+        p.apply_patches(&[(1, 5), (2, 6), (1, 8), (8, 21)]);
+        assert_eq!(&[1, 8, 6, 0, 99, 0, 0, 0, 21], p.memory());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(21, p[0]);
+    }
+
+    #[test]
+    fn test_opcode_name() {
+        assert_eq!(Some("add"), opcode_name(1));
+        assert_eq!(Some("arb"), opcode_name(9));
+        assert_eq!(Some("halt"), opcode_name(99));
+        assert_eq!(None, opcode_name(0));
+        assert_eq!(None, opcode_name(1002));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // counts mem[11] down from 3, outputting each value
+        let mem = parse_program("4,11,1001,11,-1,11,1005,11,0,99,0,3").unwrap();
+        let mut p = Processor::buffered(mem);
+        p.add_breakpoint(6);
+        p.add_breakpoint(9);
+
+        assert_eq!(RunState::Breakpoint(6), p.run_until_break());
+        assert_eq!(vec![3], p.drain_output());
+        assert_eq!(RunState::Breakpoint(6), p.run_until_break());
+        assert_eq!(vec![2], p.drain_output());
+
+        p.remove_breakpoint(6);
+        assert_eq!(RunState::Breakpoint(9), p.run_until_break());
+        assert_eq!(vec![1], p.drain_output());
+        assert_eq!(RunState::Halted, p.run_until_break());
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // counts mem[11] down from 3, outputting each value
+        let mem = parse_program("4,11,1001,11,-1,11,1005,11,0,99,0,3").unwrap();
+        let mut p = Processor::buffered(mem);
+        p.add_watchpoint(11);
+        p.add_watchpoint(20);
+
+        let hit = RunState::Watchpoint {
+            addr: 11,
+            old: 3,
+            new: 2,
+        };
+        assert_eq!(hit, p.run_until_break());
+        assert_eq!(6, p.get_ip());
+        assert_eq!(vec![3], p.drain_output());
+
+        // run ignores watchpoints
+        p.remove_watchpoint(20);
+        p.set_ip(0);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![2, 1], p.drain_output());
+
+        // writing an unchanged value doesn't stop
+        let mut p = Processor::buffered(vec![1101, 0, 5, 7, 99, 0, 0, 5]);
+        p.add_watchpoint(7);
+        assert_eq!(RunState::Halted, p.run_until_break());
+
+        // a hit while stepping isn't reported later
+        let mem = parse_program("4,11,1001,11,-1,11,1005,11,0,99,0,3").unwrap();
+        let mut p = Processor::buffered(mem);
+        p.add_watchpoint(11);
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        let hit = RunState::Watchpoint {
+            addr: 11,
+            old: 2,
+            new: 1,
+        };
+        assert_eq!(hit, p.run_until_break());
+        assert_eq!(6, p.get_ip());
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mem = parse_program("109,7,3,0,204,-7,99").unwrap();
+        let mut p = Processor::buffered(mem.clone());
+        assert_eq!(RunState::AwaitingInput, p.run());
+        let state = p.save_state();
+        assert_eq!(2, state.ip);
+        assert_eq!(7, state.relative_base);
+
+        let mut q = Processor::buffered(vec![]);
+        q.restore_state(state);
+        q.push_input(5);
+        assert_eq!(RunState::Halted, q.run());
+        assert_eq!(vec![5], q.drain_output());
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        // adds its input to a value computed before reading it
+        let mem = parse_program("109,3,1101,4,5,20,3,21,1,20,21,22,4,22,99").unwrap();
+        let mut p = Processor::buffered(mem);
+        assert_eq!(RunState::AwaitingInput, p.run());
+        let cp = p.checkpoint();
+
+        for input in 0..3 {
+            p.push_input(input);
+            assert_eq!(RunState::Halted, p.run());
+            assert_eq!(vec![9 + input], p.drain_output());
+            p.rollback(&cp);
+            assert_eq!(6, p.get_ip());
+            assert_eq!(3, p.get_relative_base());
+            assert_eq!(21, p.memory().len());
+        }
+    }
+
+    #[test]
+    fn test_instruction_count() {
+        let mut p = Processor::buffered(parse_program("1101,1,1,5,3,0,99").unwrap());
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert_eq!(1, p.instruction_count());
+        p.push_input(0);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(3, p.instruction_count());
+
+        // jumps to itself forever
+        let mut p = Processor::buffered(parse_program("1105,1,0").unwrap());
+        p.set_max_instructions(Some(1000));
+        assert_eq!(
+            Some(&IcmError::InstructionLimitExceeded(1000)),
+            p.run().error()
+        );
+        assert_eq!(1000, p.instruction_count());
+    }
+
+    #[test]
+    fn test_opcode_stats() {
+        // counts down from 3, then reads and outputs a value
+        let mut p = Processor::buffered(
+            parse_program("1101,3,0,16,1001,16,-1,16,1005,16,4,3,16,4,16,99").unwrap(),
+        );
+        p.set_opcode_stats(true);
+        assert_eq!(RunState::AwaitingInput, p.run());
+        p.push_input(5);
+        assert_eq!(RunState::Halted, p.run());
+
+        let mut stats: Vec<_> = p.opcode_stats().iter().map(|(&k, &v)| (k, v)).collect();
+        stats.sort();
+        assert_eq!(vec![(1, 4), (3, 1), (4, 1), (5, 3), (99, 1)], stats);
+
+        p.reset();
+        assert!(p.opcode_stats().is_empty());
+        p.set_opcode_stats(false);
+        p.push_input(5);
+        assert_eq!(RunState::Halted, p.run());
+        assert!(p.opcode_stats().is_empty());
+    }
+
+    #[test]
+    fn test_total_cost() {
+        // counts mem[11] down from 3, outputting each value
+        let mem = parse_program("4,11,1001,11,-1,11,1005,11,0,99,0,3").unwrap();
+        let mut p = Processor::buffered(mem);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(10, p.total_cost());
+        assert_eq!(p.instruction_count(), p.total_cost());
+
+        p.reset();
+        assert_eq!(0, p.total_cost());
+        p.set_opcode_costs([(1, 4), (4, 10), (99, 0)].iter().copied().collect());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(3 * 10 + 3 * 4 + 3, p.total_cost());
+
+        p.reset();
+        p.set_opcode_costs([(4, u64::MAX)].iter().copied().collect());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(u64::MAX, p.total_cost());
+    }
+
+    #[test]
+    fn test_io_log() {
+        // echoes its input until it reads a zero
+        let mut p = Processor::buffered(parse_program("3,11,1006,11,10,4,11,1105,1,0,99").unwrap());
+        p.set_io_log(true);
+        p.send_ascii("hi");
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert_eq!("hi\n", p.recv_ascii());
+        p.push_input(0);
+        assert_eq!(RunState::Halted, p.run());
+
+        let (input, output) = p.io_log();
+        assert_eq!(&[104, 105, 10, 0], input);
+        assert_eq!(&[104, 105, 10], output);
+
+        p.reset();
+        assert_eq!((&[][..], &[][..]), p.io_log());
+    }
+
+    #[test]
+    fn test_halted() {
+        let mut p = Processor::buffered(parse_program("104,1,99").unwrap());
+        assert!(!p.is_halted());
+        assert_eq!(RunState::Halted, p.run());
+        assert!(p.is_halted());
+        assert_eq!(2, p.instruction_count());
+
+        // the machine stays on the halt instruction
+        p.set_mem(2, 42);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Ok(StepResult::Halted), p.step());
+        assert_eq!(2, p.instruction_count());
+        assert_eq!(vec![1], p.drain_output());
+
+        p.reset();
+        assert!(!p.is_halted());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![1], p.drain_output());
+    }
+
+    #[test]
+    fn test_decode_write_params() {
+        let p = Processor::buffered(parse_program("103,0,11108,1,1,0,1207,0,1,0,99").unwrap());
+        assert_eq!(Err(IcmError::InvalidWriteParam(0)), p.decode(0).map(|_| ()));
+        assert_eq!(Err(IcmError::InvalidWriteParam(2)), p.decode(2).map(|_| ()));
+        assert!(p.decode(6).is_ok());
+    }
+
+    #[test]
+    fn test_decode_negative_params() {
+        let p = Processor::buffered(parse_program("1101,-5,3,0,99").unwrap());
+        assert_eq!("add #-5, #3 -> @0", p.decode(0).unwrap().to_string());
+        let mut p = Processor::buffered(parse_program("1101,-5,3,0,104,-7,99").unwrap());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(-2, p[0]);
+        assert_eq!(vec![-7], p.drain_output());
+
+        // negative positions can't be addressed, relative offsets can be negative
+        let p = Processor::buffered(parse_program("1,-1,0,0,4,-3,22201,-1,-2,-3,99").unwrap());
+        assert_eq!(Err(IcmError::NegativeAddress(-1)), p.decode(0).map(|_| ()));
+        assert_eq!(Err(IcmError::NegativeAddress(-3)), p.decode(4).map(|_| ()));
+        assert_eq!("add ~-1, ~-2 -> ~-3", p.decode(6).unwrap().to_string());
+        let mut p = Processor::buffered(parse_program("1,-1,0,0,99").unwrap());
+        assert_eq!(Some(&IcmError::NegativeAddress(-1)), p.run().error());
+    }
+
+    #[test]
+    fn test_builder() {
+        use std::sync::mpsc::channel;
+
+        let (send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::builder()
+            .program(parse_program("99,3,0,204,-1,1105,1,5").unwrap())
+            .input(recv_in)
+            .output(send_out)
+            .ip(1)
+            .relative_base(1)
+            .max_instructions(10)
+            .build();
+
+        send_in.send(42).unwrap();
+        assert_eq!(
+            Some(&IcmError::InstructionLimitExceeded(10)),
+            p.run().error()
+        );
+        assert_eq!(Ok(42), recv_out.recv());
+
+        let p = Processor::builder().build();
+        assert!(p.memory().is_empty());
+        assert!(p.get_input().is_none());
+    }
+
+    #[test]
+    fn test_output_bound() {
+        use std::thread;
+
+        let program = parse_program("104,1,104,2,104,3,99").unwrap();
+        let mut p = Processor::builder()
+            .program(program)
+            .output_bound(1)
+            .build();
+        let recv_out = p.take_output_receiver().unwrap();
+        assert!(p.take_output_receiver().is_none());
+
+        // the second output waits until the first one is received
+        let thr = thread::spawn(move || {
+            assert_eq!(Ok(StepResult::Continued), p.step());
+            assert_eq!(Ok(StepResult::Continued), p.step());
+            p
+        });
+        assert_eq!(Ok(1), recv_out.recv());
+        let mut p = thr.join().unwrap();
+        assert_eq!(Ok(2), recv_out.recv());
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Ok(3), recv_out.recv());
+        assert!(p.drain_output().is_empty());
+    }
+
+    #[test]
+    fn test_with_input_sender() {
+        use std::thread;
+
+        let (send_out, recv_out) = channel();
+        let (mut p, send_in) = Processor::with_input_sender(vec![3, 0, 4, 0, 99], send_out);
+        let thr = thread::spawn(move || p.run());
+        send_in.send(8).unwrap();
+        assert_eq!(Ok(8), recv_out.recv());
+        assert_eq!(RunState::Halted, thr.join().unwrap());
+
+        let (send_out, _recv_out) = channel();
+        let (mut p, send_in) = Processor::with_input_sender(vec![3, 0, 99], send_out);
+        drop(send_in);
+        assert_eq!(RunState::InputClosed, p.run());
+    }
+
+    #[test]
+    fn test_ip_and_relative_base() {
+        let mut p = Processor::buffered(parse_program("109,-2,204,3,99,7").unwrap());
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert_eq!(2, p.get_ip());
+        assert_eq!(-2, p.get_relative_base());
+
+        p.set_relative_base(2);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(4, p.get_ip());
+        assert_eq!(vec![7], p.drain_output());
+    }
+
+    #[test]
+    fn test_code_write_hook() {
+        use std::sync::{Arc, Mutex};
+
+        // writes ahead of the IP, into the executed first instruction and past the code
+        let mem = parse_program("1101,0,42,1,1101,7,0,0,1101,1,2,14,99").unwrap();
+        let mut p = Processor::buffered(mem);
+        let writes = Arc::new(Mutex::new(vec![]));
+        let hook_writes = Arc::clone(&writes);
+        p.set_code_write_hook(Box::new(move |addr, val| {
+            hook_writes.lock().unwrap().push((addr, val));
+        }));
+
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Some(3), p.get_mem(14));
+        assert_eq!(vec![(0, 7)], *writes.lock().unwrap());
+    }
+
+    #[test]
+    fn test_rebind_buffered() {
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::new(
+            0,
+            parse_program("3,11,3,12,1,11,12,13,4,13,99").unwrap(),
+            recv_in,
+            send_out,
+        );
+        assert_eq!(vec![5], p.rebind_buffered(vec![2, 3]));
+        assert!(recv_out.recv().is_err());
+
+        p.reset();
+        assert_eq!(Vec::<Word>::new(), p.rebind_buffered(vec![2]));
+        assert!(p.is_blocked());
+    }
+
+    #[test]
+    fn test_input_fn() {
+        use std::sync::mpsc::channel;
+
+        let mut p = Processor::with_inputs(parse_program("3,0,3,0,3,0,4,0,99").unwrap(), vec![1]);
+        let mut bytes = "ok".bytes();
+        p.set_input_fn(Box::new(move || Word::from(bytes.next().unwrap())));
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![Word::from(b'k')], p.drain_output());
+
+        // pending channel input comes first, in either mode
+        for nonblocking in [true, false] {
+            let (send_in, recv_in) = channel();
+            let (send_out, recv_out) = channel();
+            let mut p = Processor::from_str("3,0,3,1,4,0,4,1,99", recv_in, send_out).unwrap();
+            p.set_nonblocking(nonblocking);
+            p.set_input_fn(Box::new(|| 7));
+            send_in.send(5).unwrap();
+            assert_eq!(RunState::Halted, p.run_with_timeout(Duration::from_secs(1)));
+            assert_eq!(vec![5, 7], recv_out.try_iter().collect::<Vec<Word>>());
+        }
+    }
+
+    #[test]
+    fn test_empty_input_value() {
+        // outputs inputs until it reads 0
+        let mut p = Processor::buffered(parse_program("3,9,4,9,1005,9,0,99,0,0").unwrap());
+        p.set_empty_input_value(Some(-1));
+        p.queue_input(&[5, 6]);
+        p.set_max_instructions(Some(12));
+        assert_eq!(
+            Some(&IcmError::InstructionLimitExceeded(12)),
+            p.run().error()
+        );
+        assert_eq!(vec![5, 6, -1, -1], p.drain_output());
+
+        p.set_empty_input_value(None);
+        p.set_max_instructions(None);
+        assert_eq!(RunState::AwaitingInput, p.run());
+        p.push_input(0);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![0], p.drain_output());
+    }
+
+    #[test]
+    fn test_override_opcode() {
+        // opcode 42 squares a value in place, inputs are always 3
+        let mut p = Processor::buffered(parse_program("3,11,42,11,1,11,11,11,4,11,99").unwrap());
+        p.override_opcode(
+            42,
+            Box::new(|p| {
+                let addr = p.get_mem(p.get_ip() + 1).unwrap() as usize;
+                let val = p.get_mem(addr).unwrap();
+                p.set_mem(addr, val * val);
+                p.set_ip(p.get_ip() + 2);
+            }),
+        );
+        p.override_opcode(
+            3,
+            Box::new(|p| {
+                let addr = p.get_mem(p.get_ip() + 1).unwrap() as usize;
+                p.set_mem(addr, 3);
+                p.set_ip(p.get_ip() + 2);
+            }),
+        );
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![18], p.drain_output());
+    }
+
+    #[test]
+    fn test_uninitialized_read_hook() {
+        use std::sync::{Arc, Mutex};
+
+        // copies address 10 to 11, then outputs 11 and 12 with a relative base of 1
+        let mem = parse_program("1001,10,0,11,109,1,204,10,204,11,99").unwrap();
+        let mut p = Processor::buffered(mem);
+        let reads = Arc::new(Mutex::new(vec![]));
+        let r = Arc::clone(&reads);
+        p.set_uninitialized_read_hook(Box::new(move |addr| r.lock().unwrap().push(addr)));
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![99, 0], p.drain_output());
+        assert_eq!(vec![12], *reads.lock().unwrap());
+
+        // cells set by the caller or written before the hook was installed
+        // aren't reported
+        let mut p = Processor::buffered(parse_program("1101,1,2,60,4,50,4,60,4,70,99").unwrap());
+        p.set_mem(50, 9);
+        assert_eq!(RunState::Paused, p.run_steps(1));
+        let reads = Arc::new(Mutex::new(vec![]));
+        let r = Arc::clone(&reads);
+        p.set_uninitialized_read_hook(Box::new(move |addr| r.lock().unwrap().push(addr)));
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![9, 3, 0], p.drain_output());
+        assert_eq!(vec![70], *reads.lock().unwrap());
+    }
+
+    #[test]
+    fn test_run_with_output_handler() {
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::from_str("104,1,104,2,3,0,104,3,99", recv_in, send_out).unwrap();
+        p.set_nonblocking(true);
+        let mut seen = vec![];
+        let state = p.run_with_output_handler(|v| seen.push(v));
+        assert_eq!(RunState::AwaitingInput, state);
+        assert_eq!(vec![1, 2], seen);
+        assert!(recv_out.try_recv().is_err());
+
+        p.push_input(0);
+        let mut seen = vec![];
+        assert_eq!(
+            RunState::Halted,
+            p.run_with_output_handler(|v| seen.push(v))
+        );
+        assert_eq!(vec![3], seen);
+
+        // the output channel is attached again afterwards
+        p.set_ip(6);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Ok(3), recv_out.recv());
+    }
+
+    #[test]
+    fn test_run_steps() {
+        let mut p = Processor::buffered(parse_program("1101,1,2,20,3,21,4,20,99").unwrap());
+        assert_eq!(RunState::Paused, p.run_steps(0));
+        assert_eq!(RunState::Paused, p.run_steps(1));
+        assert_eq!(3, p[20]);
+        assert_eq!(RunState::AwaitingInput, p.run_steps(10));
+        assert_eq!(1, p.instruction_count());
+
+        p.push_input(5);
+        assert_eq!(RunState::Paused, p.run_steps(2));
+        assert_eq!(vec![3], p.drain_output());
+        assert_eq!(RunState::Halted, p.run_steps(10));
+        assert_eq!(4, p.instruction_count());
+    }
+
+    #[test]
+    fn test_with_io() {
+        use std::sync::{Arc, Mutex};
+
+        struct Shared(Arc<Mutex<Vec<Word>>>);
+
+        impl IntcodeOutput for Shared {
+            fn write(&mut self, v: Word) -> Result<(), IcmError> {
+                self.0.lock().unwrap().write(v)
+            }
+        }
+
+        let output = Arc::new(Mutex::new(vec![]));
+        let input: VecDeque<Word> = vec![3, 4].into();
+        let mut p = Processor::with_io(
+            parse_program("3,0,3,1,4,1,4,0,3,0,99").unwrap(),
+            Box::new(input),
+            Box::new(Shared(Arc::clone(&output))),
+        );
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert_eq!(vec![4, 3], *output.lock().unwrap());
+        assert!(p.get_input().is_none());
+
+        // queued input is still consumed first
+        p.push_input(5);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(5, p[0]);
+
+        let mut v: VecDeque<Word> = vec![1, 2].into();
+        assert_eq!(Ok(Some(1)), IntcodeInput::read(&mut v));
+        assert_eq!(Ok(()), v.write(3));
+        assert_eq!(vec![2, 3], Vec::from(v));
+
+        // a closed input channel is told apart from an empty one
+        let (send_in, recv_in) = channel();
+        let mut p = Processor::with_io(
+            parse_program("3,0,99").unwrap(),
+            Box::new(recv_in),
+            Box::new(vec![]),
+        );
+        drop(send_in);
+        assert_eq!(RunState::InputClosed, p.run());
+        let mut p = FixedProcessor::<3>::new(&[3, 0, 99]).unwrap();
+        let (_, mut recv_in) = channel();
+        assert_eq!(RunState::InputClosed, p.run(&mut recv_in, &mut vec![]));
+
+        // inputs from a Vec
+        let mut p = Processor::with_io(
+            parse_program("3,0,3,1,99").unwrap(),
+            Box::new(vec![5, 6].into_iter()),
+            Box::new(vec![]),
+        );
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(&[5, 6, 3, 1, 99], p.memory());
+
+        // a closed output channel stops the machine like a regular one
+        let (send_out, recv_out) = channel();
+        drop(recv_out);
+        let mut p = Processor::with_io(
+            parse_program("104,1,99").unwrap(),
+            Box::new(VecDeque::new()),
+            Box::new(send_out),
+        );
+        assert_eq!(RunState::OutputClosed, p.run());
+        let mut p = FixedProcessor::<3>::new(&[104, 1, 99]).unwrap();
+        let (mut send_out, _) = channel();
+        assert_eq!(
+            RunState::OutputClosed,
+            p.run(&mut VecDeque::new(), &mut send_out)
+        );
+    }
+
+    #[test]
+    fn test_drop_closes_output() {
+        use std::thread;
+
+        let (send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::new(0, vec![3, 10, 4, 10, 1105, 1, 0], recv_in, send_out);
+        let thr = thread::spawn(move || {
+            p.run();
+            drop(p);
+        });
+        send_in.send(4).unwrap();
+        assert_eq!(Ok(4), recv_out.recv());
+        drop(send_in);
+        // the processor stops on the closed input and drops its output
+        thr.join().unwrap();
+        assert!(recv_out.recv().is_err());
+
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::buffered(vec![104, 1, 99]);
+        p.set_output(send_out);
+        assert_eq!(RunState::Halted, p.run());
+        p.close_output();
+        assert_eq!(Ok(1), recv_out.recv());
+        assert!(recv_out.recv().is_err());
+        p.set_ip(0);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(vec![1], p.drain_output());
+    }
+
+    #[test]
+    fn test_shared_program() {
+        use std::thread;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let p = Processor::from_str("1101,0,0,0,99", recv_in, send_out).unwrap();
+        let program = p.shared_program();
+        let threads: Vec<_> = (1..4)
+            .map(|noun| {
+                let program = Arc::clone(&program);
+                thread::spawn(move || {
+                    let mut p = Processor::with_shared_program(program);
+                    p.set_mem(1, noun);
+                    assert_eq!(RunState::Halted, p.run());
+                    p.reset();
+                    assert_eq!(&[1101, 0, 0, 0, 99], p.memory());
+                    p.set_mem(1, noun);
+                    assert_eq!(RunState::Halted, p.run());
+                    p[0]
+                })
+            })
+            .collect();
+        let results: Vec<Word> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(vec![1, 2, 3], results);
+        assert!(Arc::ptr_eq(&program, &p.shared_program()));
+    }
+
+    #[test]
+    fn test_with_inputs() {
+        // adds pairs of inputs
+        let mem = parse_program("3,13,3,14,1,13,14,15,4,15,1105,1,0,0,0,0").unwrap();
+        let mut p = Processor::with_inputs(mem, vec![1, 2, 30, 40, 500]);
+        assert_eq!(RunState::AwaitingInput, p.run());
+        assert_eq!(vec![3, 70], p.take_output());
+        assert_eq!(2, p.get_ip());
+    }
+
+    #[test]
+    fn test_mem_diff() {
+        let mem = parse_program("1,0,0,0,99").unwrap();
+        let mut a = Processor::buffered(mem.clone());
+        let mut b = Processor::buffered(mem);
+        assert_eq!(Vec::<(usize, Word, Word)>::new(), a.mem_diff(&b));
+
+        a.set_mem(2, 4);
+        assert_eq!(RunState::Halted, a.run());
+        assert_eq!(RunState::Halted, b.run());
+        b.set_mem(7, 1);
+        assert_eq!(vec![(0, 100, 2), (2, 4, 0), (7, 0, 1)], a.mem_diff(&b));
+    }
+
+    #[test]
+    fn test_fast_path() {
+        // larger example from day 5, which uses every mode of the compare and
+        // jump instructions; the trace callback disables the fast path
+        let mem = parse_program(
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,\
+             20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+        )
+        .unwrap();
+        for input in 6..11 {
+            let mut fast = Processor::with_inputs(mem.clone(), vec![input]);
+            let mut slow = Processor::with_inputs(mem.clone(), vec![input]);
+            slow.set_trace(Box::new(|_, _| {}));
+
+            assert_eq!(RunState::Halted, fast.run());
+            assert_eq!(RunState::Halted, slow.run());
+            assert_eq!(slow.take_output(), fast.take_output());
+            assert_eq!(slow.memory(), fast.memory());
+            assert_eq!(slow.instruction_count(), fast.instruction_count());
+        }
+    }
+
+    #[test]
+    fn test_peek_instruction() {
+        let mut p = Processor::buffered(parse_program("1005,0,4,11101,99").unwrap());
+        let i = p.peek_instruction();
+        assert_eq!("JmpT(Position(0), Immediate(4))", format!("{:?}", i));
+        assert_eq!(3, i.len());
+        assert_eq!(0, p.get_ip());
+
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert_eq!("Halt", format!("{:?}", p.peek_instruction()));
+
+        p.set_ip(3);
+        assert_eq!("Unknown", format!("{:?}", p.peek_instruction()));
+        p.set_ip(100);
+        assert_eq!("Unknown", format!("{:?}", p.peek_instruction()));
+    }
+
+    #[test]
+    fn test_current_modes() {
+        let mut p = Processor::buffered(parse_program("21002,1,2,3,1,99,1208,4").unwrap());
+        assert_eq!([0, 1, 2], p.current_modes());
+        p.set_ip(4);
+        assert_eq!([0, 0, 0], p.current_modes());
+        p.set_ip(6);
+        assert_eq!([2, 1, 0], p.current_modes());
+        p.set_ip(100);
+        assert_eq!([0, 0, 0], p.current_modes());
+        p.set_mem(0, 93301);
+        p.set_ip(0);
+        assert_eq!([3, 3, 9], p.current_modes());
+    }
+
+    #[test]
+    fn test_output_closed() {
+        use std::sync::mpsc::channel;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::from_str("104,1,104,2,99", recv_in, send_out).unwrap();
+        assert_eq!(Ok(StepResult::Continued), p.step());
+        assert_eq!(Ok(1), recv_out.recv());
+
+        drop(recv_out);
+        assert_eq!(RunState::OutputClosed, p.run());
+        assert_eq!(2, p.get_ip());
+    }
+
+    #[test]
+    fn test_run_with_timeout() {
+        use std::sync::mpsc::channel;
+
+        let (send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::from_str("3,0,4,0,99", recv_in, send_out).unwrap();
+        let dur = Duration::from_millis(10);
+        assert_eq!(RunState::Timeout, p.run_with_timeout(dur));
+        assert_eq!(0, p.get_ip());
+
+        send_in.send(7).unwrap();
+        assert_eq!(RunState::Halted, p.run_with_timeout(dur));
+        assert_eq!(Ok(7), recv_out.recv());
+
+        let (send_in, recv_in) = channel();
+        let (send_out, _recv_out) = channel();
+        let mut p = Processor::from_str("3,0,99", recv_in, send_out).unwrap();
+        drop(send_in);
+        assert_eq!(RunState::InputClosed, p.run_with_timeout(dur));
+    }
+
+    #[test]
+    fn test_fork_with_io() {
+        use std::sync::mpsc::channel;
+
+        // doubles its input, relative to a base set by the first instruction
+        let program = vec![109, 12, 203, 0, 22201, 0, 0, 1, 204, 1, 99, 0];
+        let mut p = Processor::buffered(program.clone());
+        assert_eq!(Ok(StepResult::Continued), p.step());
+
+        for v in 1..4 {
+            let (send_in, recv_in) = channel();
+            let (send_out, recv_out) = channel();
+            let mut fork = p.fork_with_io(recv_in, send_out);
+            assert_eq!(2, fork.get_ip());
+            assert_eq!(12, fork.get_relative_base());
+            send_in.send(v).unwrap();
+            assert_eq!(RunState::Halted, fork.run());
+            assert_eq!(Ok(2 * v), recv_out.recv());
+
+            fork.reset();
+            assert_eq!(program, fork.memory());
+        }
+        assert_eq!(2, p.get_ip());
+    }
+
+    #[test]
+    fn test_run_to_last_output() {
+        use std::sync::mpsc::channel;
+
+        let (_send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::from_str("104,0,104,0,104,42,99", recv_in, send_out).unwrap();
+        assert_eq!(Ok(Some(42)), p.run_to_last_output());
+        assert!(recv_out.try_recv().is_err());
+
+        let mut p = Processor::buffered(vec![99]);
+        assert_eq!(Ok(None), p.run_to_last_output());
+
+        let mut p = Processor::buffered(vec![104, 1, 3, 0, 99]);
+        assert_eq!(Err(IcmError::RecvError), p.run_to_last_output());
+
+        let mut p = Processor::buffered(vec![104, 1, 42]);
+        let fault = IcmError::Faulted {
+            at: 2,
+            error: Box::new(IcmError::UnknownOpcode(42, 2)),
+            outputs: vec![1],
+        };
+        assert_eq!(Err(fault), p.run_to_last_output());
+    }
+
+    #[test]
+    fn test_run_until_outputs() {
+        // outputs (n, 2 * n) for every input n, until it reads a zero
+        let program = "3,20,1005,20,6,99,104,1,4,20,1002,20,2,21,4,21,1105,1,0";
+        let mut p = Processor::buffered(parse_program(program).unwrap());
+        p.queue_input(&[3, 5, 0]);
+        assert_eq!(Ok(vec![1, 3, 6]), p.run_until_outputs(3));
+        assert_eq!(Ok(vec![1, 5]), p.run_until_outputs(2));
+        assert_eq!(Ok(vec![10]), p.run_until_outputs(1));
+        assert_eq!(Ok(vec![]), p.run_until_outputs(0));
+        assert_eq!(Err(RunState::Halted), p.run_until_outputs(3));
+
+        let mut p = Processor::buffered(parse_program(program).unwrap());
+        p.push_input(4);
+        assert_eq!(Err(RunState::AwaitingInput), p.run_until_outputs(4));
+        assert_eq!(vec![1, 4, 8], p.drain_output());
+    }
+
+    #[test]
+    fn test_queue_input() {
+        use std::sync::mpsc::channel;
+
+        let (send_in, recv_in) = channel();
+        let (send_out, recv_out) = channel();
+        let mut p = Processor::from_str("3,0,3,0,3,0,4,0,99", recv_in, send_out).unwrap();
+        send_in.send(3).unwrap();
+        p.queue_input(&[1, 2]);
+
+        // queued values come first, then the channel is read
+        let mut order = vec![];
+        for _ in 0..3 {
+            assert_eq!(Ok(StepResult::Continued), p.step());
+            order.push(p.get_mem(0).unwrap());
+        }
+        assert_eq!(vec![1, 2, 3], order);
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(Ok(3), recv_out.recv());
+    }
+}
+
+pub mod amp {
+    use crate::icm::{parse_program, Processor, RunState, Word};
+    use std::fs;
+    use std::io;
+    use std::ops::RangeInclusive;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    /// Returns all permutations of `items`, generated with Heap's algorithm.
+    pub fn permutations(mut items: Vec<Word>) -> Vec<Vec<Word>> {
+        let mut perms = vec![items.clone()];
+        let mut c = vec![0; items.len()];
+        let mut i = 0;
+        while i < items.len() {
+            if c[i] < i {
+                if i % 2 == 0 {
+                    items.swap(0, i);
+                } else {
+                    items.swap(c[i], i);
+                }
+                perms.push(items.clone());
+                c[i] += 1;
+                i = 0;
+            } else {
+                c[i] = 0;
+                i += 1;
+            }
+        }
+        perms
+    }
+
+    /*
+    Finds the highest thruster signal over all phase setting permutations.
+    program     - Intcode program every amplifier runs.
+    phase_range - Phase settings to permute. One amplifier is used per setting.
+    feedback    - Whether the amplifiers run in a feedback loop or in series.
+     */
+    pub fn max_thruster_signal(
+        program: &[Word],
+        phase_range: RangeInclusive<Word>,
+        feedback: bool,
+    ) -> Word {
+        permutations(phase_range.collect())
+            .iter()
+            .map(|phases| {
+                if feedback {
+                    amplify_feedback(program, phases)
+                } else {
+                    amplify_series(program, phases)
+                }
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /*
+    Solves both parts of day 7 for a program file.
+    path - File holding the comma-separated amplifier program.
+    Returns the highest thruster signal with phases 0 to 4 in series and with
+    phases 5 to 9 in a feedback loop. Parse errors are reported as
+    `io::ErrorKind::InvalidData`.
+     */
+    pub fn solve_day07(path: &str) -> io::Result<(Word, Word)> {
+        let program = parse_program(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok((
+            max_thruster_signal(&program, 0..=4, false),
+            max_thruster_signal(&program, 5..=9, true),
+        ))
+    }
+
+    /*
+    Runs a chain of amplifiers in series.
+    program - Intcode program every amplifier runs.
+    phases  - Phase setting of each amplifier, in chain order.
+    Returns the output signal of the last amplifier. Panics if an amplifier fails.
+     */
+    pub fn amplify_series(program: &[Word], phases: &[Word]) -> Word {
+        let mut senders = vec![];
+        let mut receivers = vec![];
+        for _ in phases {
+            let (send, recv) = channel();
+            senders.push(send);
+            receivers.push(recv);
+        }
+        let (send_out, recv_out) = channel();
+        senders.push(send_out);
+
+        // send input for the first amplifier, then drop its only sender
+        let mut senders = senders.into_iter();
+        senders.next().unwrap().send(0).expect("Send error.");
+
+        // each amplifier outputs into the input channel of its successor and
+        // drops the sender when done, so reading more input than is there
+        // fails instead of blocking
+        for ((input, output), phase) in receivers.into_iter().zip(senders).zip(phases) {
+            let mut p = Processor::new(0, program.to_vec(), input, output);
+            p.queue_input(&[*phase]);
+            match p.run() {
+                RunState::Halted => (),
+                state => panic!("Amplifier error: {:?}", state),
+            }
+        }
+
+        recv_out.recv().expect("Could not receive output value")
+    }
+
+    /*
+    Runs a chain of amplifiers in a feedback loop.
+    program - Intcode program every amplifier runs.
+    phases  - Phase setting of each amplifier, in loop order.
+    Returns the last signal the final amplifier sent back to the first one.
+
+    Every amplifier runs on its own thread and blocks on its input channel
+    until its predecessor produces a signal. The threads are joined once all
+    amplifiers have halted. Panics if an amplifier fails.
+     */
+    pub fn amplify_feedback(program: &[Word], phases: &[Word]) -> Word {
+        if phases.is_empty() {
+            return 0;
+        }
+
+        let mut senders = vec![];
+        let mut receivers = vec![];
+        for _ in phases {
+            let (send, recv) = channel();
+            senders.push(send);
+            receivers.push(recv);
+        }
+
+        // each amplifier outputs into the input channel of its successor,
+        // the last one feeds back into the first
+        let mut threads = vec![];
+        for (n, input) in receivers.into_iter().enumerate() {
+            let output = senders[(n + 1) % senders.len()].clone();
+            let mut p = Processor::new(0, program.to_vec(), input, output);
+            // the first amplifier also gets the initial signal
+            if n == 0 {
+                p.queue_input(&[phases[n], 0]);
+            } else {
+                p.queue_input(&[phases[n]]);
+            }
+            threads.push(thread::spawn(move || {
+                let state = p.run();
+                p.close_output();
+                if let RunState::Error(e) = state {
+                    panic!("Amplifier error: {}", e);
+                }
+                p
+            }));
+        }
+        // only running amplifiers hold senders, so one that stops for any
+        // reason disconnects its successor instead of leaving it blocked
+        drop(senders);
+
+        let mut amps = vec![];
+        for thr in threads {
+            amps.push(thr.join().expect("Thread error"));
+        }
+
+        amps[0]
+            .get_input()
+            .expect("Amplifier has no input channel")
+            .recv()
+            .expect("Could not receive output value")
+    }
+
+    /*
+    Runs a feedback loop of amplifiers on the current thread.
+    processors - Buffered processors in loop order, with their inputs queued.
+    Returns the last signal the final amplifier sent back to the first one, or
+    0 if it never sent one.
+
+    The amplifiers take turns round-robin: each runs until it blocks on input
+    or halts, then its output is queued as input of its successor. This repeats
+    until all amplifiers have halted. Panics if an amplifier fails or if none
+    of them can make progress.
+     */
+    pub fn run_amplifier_loop(processors: &mut [Processor]) -> Word {
+        let len = processors.len();
+        let mut halted = vec![false; len];
+        let mut signal = 0;
+        while halted.contains(&false) {
+            let mut progress = false;
+            for n in 0..len {
+                if halted[n] {
+                    continue;
+                }
+                let count = processors[n].instruction_count();
+                match processors[n].run() {
+                    RunState::Halted => halted[n] = true,
+                    RunState::AwaitingInput => {}
+                    state => panic!("Amplifier stopped: {:?}", state),
+                }
+                progress |= processors[n].instruction_count() != count;
+
+                let output = processors[n].drain_output();
+                if n == len - 1 {
+                    signal = output.last().copied().unwrap_or(signal);
+                }
+                processors[(n + 1) % len].queue_input(&output);
+            }
+            assert!(progress, "Amplifiers are deadlocked");
+        }
+        signal
+    }
+
+    #[test]
+    fn test_amplify_series() {
+        let program = vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        assert_eq!(43210, amplify_series(&program, &[4, 3, 2, 1, 0]));
+
+        let program = vec![
+            3, 23, 3, 24, 1002, 24, 10, 24, 1002, 23, -1, 23, 101, 5, 23, 23, 1, 24, 23, 23, 4, 23,
+            99, 0, 0,
+        ];
+        assert_eq!(54321, amplify_series(&program, &[0, 1, 2, 3, 4]));
+
+        let program = vec![
+            3, 31, 3, 32, 1002, 32, 10, 32, 1001, 31, -2, 31, 1007, 31, 0, 33, 1002, 33, 7, 33, 1,
+            33, 31, 31, 1, 32, 31, 31, 4, 31, 99, 0, 0, 0,
+        ];
+        assert_eq!(65210, amplify_series(&program, &[1, 0, 4, 3, 2]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Amplifier error: InputClosed")]
+    fn test_amplify_series_failure() {
+        // reads a third input, which no amplifier provides
+        let program = vec![3, 9, 3, 10, 3, 10, 4, 10, 99, 0, 0];
+        amplify_series(&program, &[0, 1]);
+    }
+
+    #[test]
+    fn test_amplify_feedback() {
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        assert_eq!(139629729, amplify_feedback(&program, &[9, 8, 7, 6, 5]));
+
+        let program = vec![
+            3, 52, 1001, 52, -5, 52, 3, 53, 1, 52, 56, 54, 1007, 54, 5, 55, 1005, 55, 26, 1001, 54,
+            -5, 54, 1105, 1, 12, 1, 53, 54, 53, 1008, 54, 0, 55, 1001, 55, 1, 55, 2, 53, 55, 53, 4,
+            53, 1001, 56, -1, 56, 1005, 56, 6, 99, 0, 0, 0, 0, 10,
+        ];
+        assert_eq!(18216, amplify_feedback(&program, &[9, 7, 8, 5, 6]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Thread error")]
+    fn test_amplify_feedback_failure() {
+        // echoes its input, except with phase 5, which fails
+        let program = crate::asm::assemble(
+            "in @100
+             eq @100, #5 -> @101
+             jt @101, #16
+             in @102
+             out @102
+             jt #1, #9
+             data 42",
+        )
+        .unwrap();
+        amplify_feedback(&program, &[6, 7, 5]);
+    }
+
+    #[test]
+    fn test_run_amplifier_loop() {
+        let amps = |program: &[Word], phases: &[Word]| -> Vec<Processor> {
+            let mut amps: Vec<Processor> = phases
+                .iter()
+                .map(|&phase| Processor::with_inputs(program.to_vec(), vec![phase]))
+                .collect();
+            amps[0].push_input(0);
+            amps
+        };
+
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let mut processors = amps(&program, &[9, 8, 7, 6, 5]);
+        assert_eq!(139629729, run_amplifier_loop(&mut processors));
+        for phases in permutations((5..=9).collect()).iter().take(10) {
+            let mut processors = amps(&program, phases);
+            assert_eq!(
+                amplify_feedback(&program, phases),
+                run_amplifier_loop(&mut processors)
+            );
+        }
+
+        // the series program halts after one signal
+        let program = vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        let mut processors = amps(&program, &[4, 3, 2, 1, 0]);
+        assert_eq!(43210, run_amplifier_loop(&mut processors));
+        assert_eq!(0, run_amplifier_loop(&mut []));
+    }
+
+    #[test]
+    #[should_panic(expected = "Amplifiers are deadlocked")]
+    fn test_run_amplifier_loop_deadlock() {
+        let mut processors = vec![Processor::buffered(vec![3, 0, 99])];
+        run_amplifier_loop(&mut processors);
+    }
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(vec![Vec::<Word>::new()], permutations(vec![]));
+        assert_eq!(6, permutations(vec![1, 2, 3]).len());
+        assert_eq!(120, permutations((0..5).collect()).len());
+
+        let mut perms = permutations(vec![1, 2, 3, 4]);
+        perms.sort();
+        perms.dedup();
+        assert_eq!(24, perms.len());
+    }
+
+    #[test]
+    fn test_max_thruster_signal() {
+        let program = vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        assert_eq!(43210, max_thruster_signal(&program, 0..=4, false));
+        assert_eq!(210, max_thruster_signal(&program, 0..=2, false));
+        assert_eq!(6543210, max_thruster_signal(&program, 0..=6, false));
+        assert_eq!(3, max_thruster_signal(&program, 3..=3, false));
+
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        assert_eq!(139629729, max_thruster_signal(&program, 5..=9, true));
+    }
+
+    #[test]
+    fn test_solve_day07() {
+        assert_eq!((359142, 4374895), solve_day07("input").unwrap());
+
+        let path = std::env::temp_dir().join(format!("day07-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "3,0,99,x").unwrap();
+        let err = solve_day07(path).unwrap_err();
+        fs::remove_file(path).unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            solve_day07("no such file").unwrap_err().kind()
+        );
+    }
+}
+
+pub mod gravity {
+    use crate::icm::{Processor, RunState, Word};
+
+    /*
+    Runs a copy of a program with patched noun and verb, without any I/O.
+    program - Intcode program to run.
+    noun    - Value stored at address 1.
+    verb    - Value stored at address 2.
+    Returns the value left at address 0 once the program halts. Panics if it fails.
+     */
+    pub fn run_with_noun_verb(program: &[Word], noun: Word, verb: Word) -> Word {
+        run_patched(&mut Processor::buffered(program.to_vec()), noun, verb)
+    }
+
+    /// Resets `p`, patches in noun and verb, runs it and returns the value at
+    /// address 0. Panics if the program doesn't halt.
+    fn run_patched(p: &mut Processor, noun: Word, verb: Word) -> Word {
+        p.reset();
+        p.apply_patches(&[(1, noun), (2, verb)]);
+        match p.run() {
+            RunState::Halted => p.get_mem(0).unwrap_or(0),
+            state => panic!("Program did not halt: {:?}", state),
+        }
+    }
+
+    /*
+    Searches for the noun and verb that make a program produce a value.
+    program - Intcode program to run.
+    target  - Value expected at address 0 once the program halts.
+    Nouns and verbs are tried in 0..100, returns the first matching pair.
+     */
+    pub fn find_noun_verb(program: &[Word], target: Word) -> Option<(Word, Word)> {
+        // a single processor is reset for every attempt, reusing its memory
+        let mut p = Processor::buffered(program.to_vec());
+        (0..100)
+            .flat_map(|noun| (0..100).map(move |verb| (noun, verb)))
+            .find(|&(noun, verb)| run_patched(&mut p, noun, verb) == target)
+    }
+
+    #[test]
+    fn test_run_with_noun_verb() {
+        let program = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        assert_eq!(3500, run_with_noun_verb(&program, 9, 10));
+        assert_eq!(4000, run_with_noun_verb(&program, 9, 11));
+    }
+
+    #[test]
+    fn test_find_noun_verb() {
+        let program = vec![1101, 0, 0, 0, 99];
+        assert_eq!(Some((51, 99)), find_noun_verb(&program, 150));
+        assert_eq!(Some((0, 0)), find_noun_verb(&program, 0));
+        assert_eq!(None, find_noun_verb(&program, 199));
+    }
+}
+
+pub mod diagnostic {
+    use crate::icm::{execute, IcmError, Word};
+
+    /*
+    Runs the day 5 diagnostic program for a system.
+    program   - The diagnostic program.
+    system_id - ID of the system to test, the only input of the program.
+    Returns all output: a result for every test, followed by the diagnostic code.
+     */
+    pub fn run_diagnostic(program: &[Word], system_id: Word) -> Result<Vec<Word>, IcmError> {
+        execute(program, &[system_id])
+    }
+
+    #[test]
+    fn test_run_diagnostic() {
+        // outputs 999, 1000 or 1001 for system IDs below, equal to or above 8
+        let program = vec![
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+        assert_eq!(Ok(vec![999]), run_diagnostic(&program, 1));
+        assert_eq!(Ok(vec![1000]), run_diagnostic(&program, 8));
+        assert_eq!(Ok(vec![1001]), run_diagnostic(&program, 9));
+
+        // outputs 1 if the system ID is 8
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        assert_eq!(Ok(vec![0]), run_diagnostic(&program, 5));
+        assert_eq!(Ok(vec![1]), run_diagnostic(&program, 8));
+    }
+}
+
+pub mod grid {
+    use crate::icm::Word;
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    /*
+    Collects output that paints a 2D grid, like the robot of day 11 or the
+    arcade cabinet of day 13. Output is read in chunks of a fixed size: the
+    first two values of a chunk are the x and y coordinate, the third one is
+    the value of the cell. Chunks of two values mark their cell with 1, values
+    after the third are ignored.
+     */
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct OutputGrid {
+        chunk: usize,
+        pending: Vec<Word>,
+        cells: HashMap<(Word, Word), Word>,
+    }
+
+    impl OutputGrid {
+        /// Creates an empty grid. Panics if `chunk` is smaller than two.
+        pub fn new(chunk: usize) -> OutputGrid {
+            assert!(chunk >= 2, "Chunks need at least two coordinates");
+            OutputGrid {
+                chunk,
+                pending: vec![],
+                cells: HashMap::new(),
+            }
+        }
+
+        /// Paints the cells described by `values`. An incomplete chunk at the
+        /// end is kept until the next call completes it.
+        pub fn feed(&mut self, values: &[Word]) {
+            self.pending.extend_from_slice(values);
+            let complete = self.pending.len() - self.pending.len() % self.chunk;
+            for c in self
+                .pending
+                .drain(..complete)
+                .collect::<Vec<_>>()
+                .chunks(self.chunk)
+            {
+                self.cells
+                    .insert((c[0], c[1]), c.get(2).copied().unwrap_or(1));
+            }
+        }
+
+        /// Returns the value of a cell, if it was painted.
+        pub fn get(&self, x: Word, y: Word) -> Option<Word> {
+            self.cells.get(&(x, y)).copied()
+        }
+
+        pub fn cells(&self) -> &HashMap<(Word, Word), Word> {
+            &self.cells
+        }
+
+        /*
+        Draws the painted area, one line per row from the top. Cells with value
+        0 and cells that weren't painted are blank, 1 is drawn as '#' and other
+        values as their digit, or '?' if they have none.
+         */
+        pub fn render(&self) -> String {
+            self.render_with(|v| match v {
+                None | Some(0) => ' ',
+                Some(1) => '#',
+                Some(v) => u32::try_from(v)
+                    .ok()
+                    .and_then(|d| std::char::from_digit(d, 10))
+                    .unwrap_or('?'),
+            })
+        }
+
+        /// Draws the painted area with `draw` picking the character of a cell.
+        fn render_with(&self, draw: impl Fn(Option<Word>) -> char) -> String {
+            let (xs, ys): (Vec<Word>, Vec<Word>) = self.cells.keys().copied().unzip();
+            let (x0, x1) = match (xs.iter().min(), xs.iter().max()) {
+                (Some(&x0), Some(&x1)) => (x0, x1),
+                _ => return String::new(),
+            };
+            let (y0, y1) = (*ys.iter().min().unwrap(), *ys.iter().max().unwrap());
+
+            let mut image = String::new();
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    image.push(draw(self.get(x, y)));
+                }
+                image.push('\n');
+            }
+            image
+        }
+    }
+
+    pub const EMPTY: Word = 0;
+    pub const WALL: Word = 1;
+    pub const BLOCK: Word = 2;
+    pub const PADDLE: Word = 3;
+    pub const BALL: Word = 4;
+
+    /*
+    Screen of the day 13 arcade cabinet. The game outputs triples of x, y and
+    tile id, except for the triple (-1, 0, score), which sets the score shown
+    on the segment display instead of drawing a tile.
+     */
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TileScreen {
+        grid: OutputGrid,
+        pending: Vec<Word>,
+        score: Word,
+    }
+
+    impl TileScreen {
+        pub fn new() -> TileScreen {
+            TileScreen {
+                grid: OutputGrid::new(3),
+                pending: vec![],
+                score: 0,
+            }
+        }
+
+        /// Draws tiles and updates the score from game output. An incomplete
+        /// triple at the end is kept until the next call completes it.
+        pub fn feed(&mut self, values: &[Word]) {
+            self.pending.extend_from_slice(values);
+            let complete = self.pending.len() - self.pending.len() % 3;
+            for c in self.pending.drain(..complete).collect::<Vec<_>>().chunks(3) {
+                match c {
+                    [-1, 0, score] => self.score = *score,
+                    tile => self.grid.feed(tile),
+                }
+            }
+        }
+
+        /// Returns the tile id at a position, if a tile was drawn there.
+        pub fn get(&self, x: Word, y: Word) -> Option<Word> {
+            self.grid.get(x, y)
+        }
+
+        pub fn score(&self) -> Word {
+            self.score
+        }
+
+        /// Number of block tiles on the screen.
+        pub fn block_count(&self) -> usize {
+            self.grid.cells().values().filter(|&&t| t == BLOCK).count()
+        }
+
+        /// Draws the screen: walls as '#', blocks as '=', the paddle as '-'
+        /// and the ball as 'o'. Unknown tile ids are drawn as '?'.
+        pub fn render(&self) -> String {
+            self.grid.render_with(|tile| match tile {
+                None | Some(EMPTY) => ' ',
+                Some(WALL) => '#',
+                Some(BLOCK) => '=',
+                Some(PADDLE) => '-',
+                Some(BALL) => 'o',
+                Some(_) => '?',
+            })
+        }
+    }
+
+    impl Default for TileScreen {
+        fn default() -> Self {
+            TileScreen::new()
+        }
+    }
+
+    #[test]
+    fn test_tile_screen() {
+        let mut screen = TileScreen::new();
+        screen.feed(&[0, 0, WALL, 1, 0, WALL, 2, 0, WALL, 0, 1, BLOCK, 1, 1]);
+        screen.feed(&[BLOCK, 2, 1, BALL, -1, 0, 12, 1, 2, PADDLE, 2, 2]);
+        assert_eq!(2, screen.block_count());
+        assert_eq!(12, screen.score());
+        assert_eq!(None, screen.get(-1, 0));
+        assert_eq!(Some(PADDLE), screen.get(1, 2));
+        assert_eq!("###\n==o\n - \n", screen.render());
+
+        // an empty tile completes the pending triple, the ball destroys a block
+        screen.feed(&[EMPTY, 1, 1, EMPTY, -1, 0, 42]);
+        assert_eq!(1, screen.block_count());
+        assert_eq!(42, screen.score());
+        assert_eq!("###\n= o\n - \n", screen.render());
+    }
+
+    #[test]
+    fn test_output_grid() {
+        let mut grid = OutputGrid::new(3);
+        grid.feed(&[1, 2, 3, 6, 5, 4]);
+        grid.feed(&[-1, 0]);
+        assert_eq!(None, grid.get(-1, 0));
+        grid.feed(&[12345, 2, 2, 1, 2, 2, 0]);
+        assert_eq!(Some(12345), grid.get(-1, 0));
+        assert_eq!(Some(0), grid.get(2, 2));
+        assert_eq!(4, grid.cells().len());
+        assert_eq!(
+            "?       \n        \n  3     \n        \n        \n       4\n",
+            grid.render()
+        );
+
+        let mut grid = OutputGrid::new(2);
+        grid.feed(&[0, 0, 1, 1, 0, 1]);
+        assert_eq!("# \n##\n", grid.render());
+
+        // values that only fit a digit once truncated
+        let mut grid = OutputGrid::new(3);
+        grid.feed(&[0, 0, (1 << 32) + 3, 1, 0, -1]);
+        assert_eq!("??\n", grid.render());
+        assert_eq!("", OutputGrid::new(3).render());
+    }
+}
+
+pub mod network {
+    use crate::icm::{Processor, RunState, Word};
+    use std::convert::TryInto;
+    use std::thread;
+
+    /*
+    Runs processors concurrently, each on its own thread, until all stopped.
+    processors - Processors with their channels already connected.
+    Returns the values each processor output, in the order of `processors`.
+    A processor stops early if the receiver of its output is gone, the value
+    it failed to send is not included. Panics if a processor fails.
+     */
+    pub fn run_network(processors: Vec<Processor>) -> Vec<Vec<Word>> {
+        let threads: Vec<_> = processors
+            .into_iter()
+            .map(|mut p| {
+                thread::spawn(move || {
+                    p.set_io_log(true);
+                    if let RunState::Error(e) = p.run() {
+                        panic!("Processor error: {}", e);
+                    }
+                    p.io_log().1.to_vec()
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thr| thr.join().expect("Thread error"))
+            .collect()
+    }
+
+    /// A packet as output by a computer: destination address, X and Y.
+    pub type Packet = (Word, Word, Word);
+
+    /*
+    Network of computers exchanging packets, as in day 23. Every computer runs
+    the same program on a buffered processor and is booted with its address as
+    the first input. A computer outputs a packet as three values: destination
+    address, X and Y. Packets for computers in the network are queued as their
+    input, a computer polling an empty queue reads -1.
+     */
+    pub struct Network {
+        computers: Vec<Processor>,
+        pending: Vec<Vec<Word>>,
+        queued: Vec<usize>,
+        idle: bool,
+    }
+
+    impl Network {
+        /// Boots `size` computers running `program`, with addresses `0..size`.
+        pub fn new(program: &[Word], size: usize) -> Network {
+            let computers = (0..size)
+                .map(|addr| {
+                    let mut p = Processor::buffered(program.to_vec());
+                    p.push_input(addr as Word);
+                    p
+                })
+                .collect();
+            Network {
+                computers,
+                pending: vec![vec![]; size],
+                queued: vec![0; size],
+                idle: false,
+            }
+        }
+
+        /// Queues a packet for computer `dest`, e.g. from a NAT. Returns
+        /// false if there is no computer with that address.
+        pub fn send(&mut self, dest: usize, x: Word, y: Word) -> bool {
+            match self.computers.get_mut(dest) {
+                Some(computer) => {
+                    computer.queue_input(&[x, y]);
+                    self.queued[dest] += 1;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        /*
+        Runs every computer in address order until it waits for input again,
+        routing the packets it output. A computer with no packets queued reads
+        -1 once. Returns the packets addressed to computers outside the network
+        in the order they were sent. Panics if a computer fails.
+         */
+        pub fn round(&mut self) -> Vec<Packet> {
+            let mut external = vec![];
+            let mut busy = false;
+            for n in 0..self.computers.len() {
+                if self.computers[n].is_halted() {
+                    continue;
+                }
+                if self.queued[n] == 0 {
+                    self.computers[n].push_input(-1);
+                } else {
+                    busy = true;
+                    self.queued[n] = 0;
+                }
+                match self.computers[n].run() {
+                    RunState::Halted | RunState::AwaitingInput => {}
+                    state => panic!("Computer {} stopped: {:?}", n, state),
+                }
+
+                // a packet may be split across runs
+                let output = self.computers[n].drain_output();
+                self.pending[n].extend(output);
+                let len = self.pending[n].len() / 3 * 3;
+                let packets: Vec<Word> = self.pending[n].drain(..len).collect();
+                for packet in packets.chunks(3) {
+                    busy = true;
+                    let (dest, x, y) = (packet[0], packet[1], packet[2]);
+                    let delivered = dest.try_into().is_ok_and(|n| self.send(n, x, y));
+                    if !delivered {
+                        external.push((dest, x, y));
+                    }
+                }
+            }
+            self.idle = !busy;
+            external
+        }
+
+        /// Whether no computer received or sent a packet in the last round.
+        pub fn is_idle(&self) -> bool {
+            self.idle
+        }
+    }
+
+    #[test]
+    fn test_network() {
+        // receiving (x, y) sends (x + 1, y + 1) to address y
+        let program = vec![
+            3, 100, 3, 101, 1008, 101, -1, 102, 1005, 102, 2, 3, 103, 4, 103, 1001, 101, 1, 101, 4,
+            101, 1001, 103, 1, 103, 4, 103, 1105, 1, 2,
+        ];
+        let mut network = Network::new(&program, 2);
+        assert!(!network.is_idle());
+        assert!(!network.send(2, 0, 1));
+        assert!(network.send(0, 0, 1));
+        assert_eq!(vec![(2, 2, 3)], network.round());
+        assert!(!network.is_idle());
+        assert!(network.round().is_empty());
+        assert!(network.is_idle());
+
+        // the packet from 1 reaches 0 only in the next round
+        assert!(network.send(1, 5, 0));
+        assert!(network.round().is_empty());
+        assert!(!network.is_idle());
+        assert_eq!(vec![(2, 8, 3)], network.round());
+        assert!(!network.is_idle());
+        assert!(network.round().is_empty());
+        assert!(network.is_idle());
+    }
+
+    #[test]
+    fn test_run_network() {
+        use std::sync::mpsc::channel;
+
+        // a doubler feeding an incrementer
+        let (send_a, recv_a) = channel();
+        let (send_b, recv_b) = channel();
+        let (send_out, recv_out) = channel();
+        let doubler = Processor::new(0, vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0], recv_a, send_b);
+        let incrementer =
+            Processor::new(0, vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0], recv_b, send_out);
+        send_a.send(20).unwrap();
+        let outputs = run_network(vec![doubler, incrementer]);
+        assert_eq!(vec![vec![40], vec![41]], outputs);
+        assert_eq!(Ok(41), recv_out.recv());
+
+        assert!(run_network(vec![]).is_empty());
+    }
+}
+
+pub mod asm {
+    use crate::icm::{decode_instruction, opcode_name, Word};
+    use std::error::Error;
+    use std::fmt;
+
+    /// Error in an assembly source, with the 1-based line it occurred on.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum AsmError {
+        /// A mnemonic that names no instruction.
+        UnknownMnemonic(usize, String),
+        /// An instruction with the wrong number of parameters, with the
+        /// number it takes and the number it was given.
+        ParamCount(usize, usize, usize),
+        /// A parameter that isn't a number with an optional mode sigil.
+        InvalidParam(usize, String),
+    }
+
+    impl fmt::Display for AsmError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                AsmError::UnknownMnemonic(line, name) => {
+                    write!(f, "line {}: unknown mnemonic {}", line, name)
+                }
+                AsmError::ParamCount(line, expected, found) => write!(
+                    f,
+                    "line {}: expected {} parameters, found {}",
+                    line, expected, found
+                ),
+                AsmError::InvalidParam(line, param) => {
+                    write!(f, "line {}: invalid parameter {}", line, param)
+                }
+            }
+        }
+    }
+
+    impl Error for AsmError {}
+
+    /*
+    Assembles a program written with the mnemonics of `opcode_name`, one
+    instruction per line, e.g. `add @4, #3, ~0`. Parameters are separated by
+    commas, `->` is accepted before the last one as written by `Instr`'s
+    `Display` impl. Modes are given by a sigil: '@' or none for position, '#'
+    immediate and '~' relative. `data` followed by numbers emits them as they
+    are. Mnemonics are case-insensitive.
+
+    A '#' that isn't directly followed by a digit or minus sign starts a
+    comment, which extends to the end of the line.
+     */
+    pub fn assemble(src: &str) -> Result<Vec<Word>, AsmError> {
+        let mut program = vec![];
+        for (n, line) in src.lines().enumerate() {
+            let line_no = n + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (mnemonic, rest) = match line.find(char::is_whitespace) {
+                Some(i) => (&line[..i], line[i..].replace("->", ",")),
+                None => (line, String::new()),
+            };
+            let params: Vec<&str> = rest
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .collect();
+            let invalid = |p: &str| AsmError::InvalidParam(line_no, p.to_string());
+
+            let mnemonic = mnemonic.to_lowercase();
+            if mnemonic == "data" {
+                for p in params {
+                    program.push(p.parse().map_err(|_| invalid(p))?);
+                }
+                continue;
+            }
+            let opcode = (1..=9)
+                .chain(Some(99))
+                .find(|&op| opcode_name(op) == Some(mnemonic.as_str()))
+                .ok_or_else(|| AsmError::UnknownMnemonic(line_no, mnemonic.clone()))?;
+            let expected = decode_instruction(&[opcode], 0)
+                .map(|i| i.len() - 1)
+                .unwrap_or(0);
+            if params.len() != expected {
+                return Err(AsmError::ParamCount(line_no, expected, params.len()));
+            }
+
+            let mut word = opcode;
+            let mut values = vec![];
+            for (nth, p) in params.iter().enumerate() {
+                let (mode, digits) = match p.chars().next() {
+                    Some('@') => (0, &p[1..]),
+                    Some('#') => (1, &p[1..]),
+                    Some('~') => (2, &p[1..]),
+                    _ => (0, *p),
+                };
+                values.push(digits.trim().parse::<Word>().map_err(|_| invalid(p))?);
+                word += mode * Word::pow(10, nth as u32 + 2);
+            }
+            program.push(word);
+            program.extend(values);
+        }
+        Ok(program)
+    }
+
+    /// Cuts off a comment, but not a '#' that marks an immediate parameter.
+    fn strip_comment(line: &str) -> &str {
+        for (i, c) in line.char_indices() {
+            if c == '#' {
+                match line[i + 1..].chars().next() {
+                    Some(d) if d.is_ascii_digit() || d == '-' => {}
+                    _ => return &line[..i],
+                }
+            }
+        }
+        line
+    }
+
+    #[test]
+    fn test_assemble() {
+        use crate::icm::parse_program;
+
+        let src = "
+            # outputs 1 if the input is equal to 8, 0 otherwise
+            in @9
+            eq @9, #8 -> @9   # compare in place
+            OUT 9
+            halt
+            data -1
+        ";
+        assert_eq!(
+            parse_program("3,9,1008,9,8,9,4,9,99,-1").unwrap(),
+            assemble(src).unwrap()
+        );
+        assert_eq!(
+            vec![109, 1, 204, -1, 21101, -3, 7, 4],
+            assemble("arb #1\nout ~-1\nadd #-3, #7, ~4").unwrap()
+        );
+        assert_eq!(Ok(vec![]), assemble("# nothing here\n\n"));
+
+        assert_eq!(
+            Err(AsmError::UnknownMnemonic(2, "nop".to_string())),
+            assemble("halt\nnop")
+        );
+        assert_eq!(Err(AsmError::ParamCount(1, 3, 2)), assemble("add 1, 2"));
+        assert_eq!(Err(AsmError::ParamCount(1, 0, 1)), assemble("halt 1"));
+        assert_eq!(
+            Err(AsmError::InvalidParam(1, "$4".to_string())),
+            assemble("out $4")
+        );
+        assert_eq!(
+            Err(AsmError::InvalidParam(1, "x".to_string())),
+            assemble("data 1, x")
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod test_util {
+    use crate::icm::{execute, IcmError, Processor, RunState, StepResult, Word};
+
+    /// Known programs from the puzzles, with their inputs and expected output.
+    pub const CORPUS: &[(&str, &[Word], &[Word])] = &[
+        // day 2: no output, but it has to halt
+        ("1,9,10,3,2,3,11,0,99,30,40,50", &[], &[]),
+        // day 5: echo, comparisons in position and immediate mode
+        ("3,0,4,0,99", &[42], &[42]),
+        ("3,9,8,9,10,9,4,9,99,-1,8", &[8], &[1]),
+        ("3,9,7,9,10,9,4,9,99,-1,8", &[8], &[0]),
+        ("3,3,1108,-1,8,3,4,3,99", &[7], &[0]),
+        ("3,3,1107,-1,8,3,4,3,99", &[7], &[1]),
+        // day 5: jumps
+        ("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9", &[0], &[0]),
+        ("3,3,1105,-1,9,1101,0,0,12,4,12,99,1", &[5], &[1]),
+        (
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+            &[9],
+            &[1001],
+        ),
+        // day 7: a single amplifier of the first example
+        ("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0", &[4, 0], &[4]),
+        // day 9: quine and large numbers
+        (
+            "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+            &[],
+            &[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99],
+        ),
+        ("1102,34915192,34915192,7,4,7,99,0", &[], &[1219070632396864]),
+        ("104,1125899906842624,99", &[], &[1125899906842624]),
+    ];
+
+    /*
+    Asserts that a program produces the expected output.
+    program  - Intcode program to run.
+    inputs   - Values for the input instructions, in order.
+    expected - Output the program has to produce before it halts.
+    The program runs twice: as usual, and traced, which bypasses the fast path
+    of the interpreter. Both runs have to produce the expected output.
+     */
+    pub fn assert_same_output(program: &[Word], inputs: &[Word], expected: &[Word]) {
+        assert_eq!(Ok(expected.to_vec()), execute(program, inputs));
+
+        let mut p = Processor::with_inputs(program.to_vec(), inputs.to_vec());
+        p.set_trace(Box::new(|_, _| {}));
+        assert_eq!(RunState::Halted, p.run());
+        assert_eq!(expected.to_vec(), p.drain_output());
+    }
+
+    /// First point where two processors run by `run_lockstep` disagree.
+    /// `step` counts the instructions executed by each processor so far.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum LockstepMismatch {
+        Step {
+            step: usize,
+            a: Result<StepResult, IcmError>,
+            b: Result<StepResult, IcmError>,
+        },
+        Ip {
+            step: usize,
+            a: usize,
+            b: usize,
+        },
+        Memory {
+            step: usize,
+            addr: usize,
+            a: Word,
+            b: Word,
+        },
+        Output {
+            step: usize,
+            a: Vec<Word>,
+            b: Vec<Word>,
+        },
+    }
+
+    /*
+    Steps two processors side by side until both halt, block or fail, and
+    compares them after every instruction.
+    a, b - Processors to compare, typically the same program and inputs on a
+           reference and a modified interpreter. Both should collect their
+           output in buffers, which get drained as it is compared.
+    Returns the first divergence in step result, IP, memory or output.
+     */
+    pub fn run_lockstep(a: &mut Processor, b: &mut Processor) -> Result<(), LockstepMismatch> {
+        for step in 1.. {
+            let (ra, rb) = (a.step(), b.step());
+            if ra != rb {
+                return Err(LockstepMismatch::Step { step, a: ra, b: rb });
+            }
+            if a.get_ip() != b.get_ip() {
+                return Err(LockstepMismatch::Ip {
+                    step,
+                    a: a.get_ip(),
+                    b: b.get_ip(),
+                });
+            }
+            if let Some(&(addr, va, vb)) = a.mem_diff(b).first() {
+                return Err(LockstepMismatch::Memory {
+                    step,
+                    addr,
+                    a: va,
+                    b: vb,
+                });
+            }
+            let (oa, ob) = (a.drain_output(), b.drain_output());
+            if oa != ob {
+                return Err(LockstepMismatch::Output { step, a: oa, b: ob });
+            }
+            if ra != Ok(StepResult::Continued) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_corpus() {
+        use crate::icm::parse_program;
+
+        for (program, inputs, expected) in CORPUS {
+            let program = parse_program(program).unwrap();
+            assert_same_output(&program, inputs, expected);
+        }
+    }
+
+    #[test]
+    fn test_lockstep() {
+        use crate::icm::parse_program;
+
+        for (program, inputs, _) in CORPUS {
+            let program = parse_program(program).unwrap();
+            let mut a = Processor::with_inputs(program.clone(), inputs.to_vec());
+            let mut b = Processor::with_inputs(program, inputs.to_vec());
+            b.set_trace(Box::new(|_, _| {}));
+            assert_eq!(Ok(()), run_lockstep(&mut a, &mut b));
+        }
+    }
+
+    #[test]
+    fn test_lockstep_mismatch() {
+        // add 1 + 2, the second program multiplies instead
+        let mut a = Processor::with_inputs(vec![1101, 1, 2, 7, 4, 7, 99, 0], vec![]);
+        let mut b = Processor::with_inputs(vec![1102, 1, 2, 7, 4, 7, 99, 0], vec![]);
+        assert_eq!(
+            Err(LockstepMismatch::Memory {
+                step: 1,
+                addr: 0,
+                a: 1101,
+                b: 1102
+            }),
+            run_lockstep(&mut a, &mut b)
+        );
+
+        // same code, but different input ends up in memory
+        let mut a = Processor::with_inputs(vec![3, 5, 4, 5, 99, 0], vec![1]);
+        let mut b = Processor::with_inputs(vec![3, 5, 4, 5, 99, 0], vec![2]);
+        assert_eq!(
+            Err(LockstepMismatch::Memory {
+                step: 1,
+                addr: 5,
+                a: 1,
+                b: 2
+            }),
+            run_lockstep(&mut a, &mut b)
+        );
+
+        // only the first processor has input to read
+        let mut a = Processor::with_inputs(vec![3, 5, 4, 5, 99, 0], vec![1]);
+        let mut b = Processor::with_inputs(vec![3, 5, 4, 5, 99, 0], vec![]);
+        assert_eq!(
+            Err(LockstepMismatch::Step {
+                step: 1,
+                a: Ok(StepResult::Continued),
+                b: Ok(StepResult::Blocked)
+            }),
+            run_lockstep(&mut a, &mut b)
+        );
+    }
 }